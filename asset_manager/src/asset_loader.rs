@@ -110,7 +110,7 @@ fn match_methods(tx: &UnboundedSender<AssetEvent>, result: Result<notify::Event,
     Ok(event) => {
       update_files(tx, event);
     }
-    Err(e) => eprintln!("watch error: {:?}", e),
+    Err(e) => logger::error!("watch error: {:?}", e),
   }
 }
 
@@ -0,0 +1,42 @@
+use std::cell::Cell;
+
+thread_local! {
+  static TASK: Cell<Option<&'static str>> = const { Cell::new(None) };
+  static FRAME: Cell<Option<u64>> = const { Cell::new(None) };
+}
+
+/// the task and frame every line logged from this thread is tagged with
+pub fn current() -> (Option<&'static str>, Option<u64>) {
+  (TASK.with(Cell::get), FRAME.with(Cell::get))
+}
+
+/// the UpdateManager calls this on its own thread at the start of every frame
+pub fn set_frame(frame: Option<u64>) {
+  FRAME.with(|current| current.set(frame));
+}
+
+/// tags everything logged from this thread with the task, until the scope is dropped.
+/// worker threads don't know the frame, so it's given along with the task.
+pub fn enter_task(task: &'static str, frame: Option<u64>) -> TaskScope {
+  let scope = TaskScope {
+    task: TASK.with(|current| current.replace(Some(task))),
+    frame: FRAME.with(Cell::get),
+  };
+  if frame.is_some() {
+    set_frame(frame);
+  }
+  scope
+}
+
+/// puts back whatever was there before enter_task
+pub struct TaskScope {
+  task: Option<&'static str>,
+  frame: Option<u64>,
+}
+
+impl Drop for TaskScope {
+  fn drop(&mut self) {
+    TASK.with(|current| current.set(self.task));
+    set_frame(self.frame);
+  }
+}
@@ -0,0 +1,287 @@
+pub mod context;
+pub mod sink;
+
+use std::collections::HashMap;
+use std::sync::{Arc, OnceLock, RwLock};
+use std::time::SystemTime;
+
+pub use context::{TaskScope, enter_task, set_frame};
+pub use sink::{FileSink, RingBufferSink, Sink, StdoutSink};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Level {
+  Trace,
+  Debug,
+  Info,
+  Warn,
+  Error,
+}
+
+impl Level {
+  pub fn name(&self) -> &'static str {
+    match self {
+      Level::Trace => "trace",
+      Level::Debug => "debug",
+      Level::Info => "info",
+      Level::Warn => "warn",
+      Level::Error => "error",
+    }
+  }
+}
+
+impl std::fmt::Display for Level {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.write_str(self.name())
+  }
+}
+
+/// one log line, handed to every sink
+#[derive(Clone, Debug)]
+pub struct Record {
+  pub level: Level,
+  /// the task's label when logged from inside a task, otherwise the module it was logged from
+  pub target: Arc<str>,
+  /// the task that was running on the thread, see enter_task
+  pub task: Option<&'static str>,
+  /// the UpdateManager's frame, see set_frame
+  pub frame: Option<u64>,
+  pub time: SystemTime,
+  pub message: String,
+}
+
+impl std::fmt::Display for Record {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "{:5}", self.level.name())?;
+    if let Some(frame) = self.frame {
+      write!(f, " [frame {}]", frame)?;
+    }
+    // the target is the task's label when there's a task
+    write!(f, " {}: {}", self.target, self.message)
+  }
+}
+
+struct Logger {
+  sinks: RwLock<Vec<Arc<dyn Sink>>>,
+  level: RwLock<Level>,
+  /// overrides the level for a target, eg: a noisy task
+  target_levels: RwLock<HashMap<String, Level>>,
+}
+
+/// every program starts out logging Info and up to stdout
+fn logger() -> &'static Logger {
+  static LOGGER: OnceLock<Logger> = OnceLock::new();
+  LOGGER.get_or_init(|| Logger {
+    sinks: RwLock::new(vec![Arc::new(StdoutSink)]),
+    level: RwLock::new(Level::Info),
+    target_levels: RwLock::new(HashMap::new()),
+  })
+}
+
+pub fn add_sink(sink: Arc<dyn Sink>) {
+  if let Ok(mut sinks) = logger().sinks.write() {
+    sinks.push(sink);
+  }
+}
+
+/// removes every sink, including the default stdout one
+pub fn clear_sinks() {
+  if let Ok(mut sinks) = logger().sinks.write() {
+    sinks.clear();
+  }
+}
+
+/// the lowest level that gets logged, for targets without a level of their own
+pub fn set_level(level: Level) {
+  if let Ok(mut current) = logger().level.write() {
+    *current = level;
+  }
+}
+
+/// the target is a task's label, or a module path (eg: "trick::update_manager::channel")
+pub fn set_target_level(target: &str, level: Level) {
+  if let Ok(mut target_levels) = logger().target_levels.write() {
+    target_levels.insert(target.to_string(), level);
+  }
+}
+
+pub fn clear_target_level(target: &str) {
+  if let Ok(mut target_levels) = logger().target_levels.write() {
+    target_levels.remove(target);
+  }
+}
+
+pub fn enabled(level: Level, target: &str) -> bool {
+  let logger = logger();
+  let target_level = logger
+    .target_levels
+    .read()
+    .ok()
+    .and_then(|target_levels| target_levels.get(target).copied());
+  let minimum = target_level.unwrap_or_else(|| {
+    logger
+      .level
+      .read()
+      .map(|level| *level)
+      .unwrap_or(Level::Info)
+  });
+  level >= minimum
+}
+
+/// used by the macros, the module is the target unless a task is running on this thread
+pub fn log(level: Level, module: &'static str, message: std::fmt::Arguments) {
+  let (task, frame) = context::current();
+  let target = task.unwrap_or(module);
+  if !enabled(level, target) {
+    return;
+  }
+
+  let record = Record {
+    level,
+    target: Arc::from(target),
+    task,
+    frame,
+    time: SystemTime::now(),
+    message: message.to_string(),
+  };
+  if let Ok(sinks) = logger().sinks.read() {
+    for sink in sinks.iter() {
+      sink.write(&record);
+    }
+  }
+}
+
+pub fn flush() {
+  if let Ok(sinks) = logger().sinks.read() {
+    for sink in sinks.iter() {
+      sink.flush();
+    }
+  }
+}
+
+#[macro_export]
+macro_rules! log {
+  ($level:expr, $($arg:tt)+) => {
+    $crate::log($level, module_path!(), format_args!($($arg)+))
+  };
+}
+
+#[macro_export]
+macro_rules! error {
+  ($($arg:tt)+) => { $crate::log!($crate::Level::Error, $($arg)+) };
+}
+
+#[macro_export]
+macro_rules! warn {
+  ($($arg:tt)+) => { $crate::log!($crate::Level::Warn, $($arg)+) };
+}
+
+#[macro_export]
+macro_rules! info {
+  ($($arg:tt)+) => { $crate::log!($crate::Level::Info, $($arg)+) };
+}
+
+#[macro_export]
+macro_rules! debug {
+  ($($arg:tt)+) => { $crate::log!($crate::Level::Debug, $($arg)+) };
+}
+
+#[macro_export]
+macro_rules! trace {
+  ($($arg:tt)+) => { $crate::log!($crate::Level::Trace, $($arg)+) };
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// the logger is shared by every test, so each one looks only at the lines it logged
+  fn capture() -> RingBufferSink {
+    let sink = RingBufferSink::new(64);
+    add_sink(Arc::new(sink.clone()));
+    sink
+  }
+
+  fn logged(sink: &RingBufferSink, message: &str) -> Vec<Record> {
+    sink
+      .records()
+      .into_iter()
+      .filter(|record| record.message == message)
+      .collect()
+  }
+
+  #[test]
+  fn target_levels_filter_their_own_target() {
+    set_target_level("quiet task", Level::Warn);
+    set_target_level("chatty task", Level::Trace);
+
+    assert!(!enabled(Level::Info, "quiet task"));
+    assert!(enabled(Level::Warn, "quiet task"));
+    assert!(enabled(Level::Error, "quiet task"));
+    assert!(enabled(Level::Trace, "chatty task"));
+
+    clear_target_level("quiet task");
+    clear_target_level("chatty task");
+    assert_eq!(
+      enabled(Level::Info, "quiet task"),
+      enabled(Level::Info, "some other task")
+    );
+  }
+
+  #[test]
+  fn filtered_lines_never_reach_the_sinks() {
+    let sink = capture();
+    set_target_level("filtered task", Level::Warn);
+    {
+      let _scope = enter_task("filtered task", None);
+      info!("filtered info");
+      warn!("filtered warn");
+      error!("filtered error");
+    }
+    clear_target_level("filtered task");
+
+    assert!(logged(&sink, "filtered info").is_empty());
+    assert_eq!(logged(&sink, "filtered warn")[0].level, Level::Warn);
+    assert_eq!(logged(&sink, "filtered error")[0].level, Level::Error);
+  }
+
+  #[test]
+  fn lines_are_tagged_with_the_task_and_frame() {
+    let sink = capture();
+    set_frame(Some(3));
+    warn!("before the task");
+    {
+      let _scope = enter_task("context task", Some(7));
+      warn!("inside the task");
+      {
+        // a task without a frame of its own keeps the one it's running in
+        let _inner = enter_task("inner task", None);
+        warn!("inside the inner task");
+      }
+      warn!("back in the task");
+    }
+    warn!("after the task");
+    set_frame(None);
+
+    let before = &logged(&sink, "before the task")[0];
+    assert_eq!((before.task, before.frame), (None, Some(3)));
+    assert_eq!(&*before.target, module_path!());
+
+    let inside = &logged(&sink, "inside the task")[0];
+    assert_eq!((inside.task, inside.frame), (Some("context task"), Some(7)));
+    // the target is the task, so target levels work for tasks
+    assert_eq!(&*inside.target, "context task");
+    assert_eq!(
+      inside.to_string(),
+      "warn  [frame 7] context task: inside the task"
+    );
+
+    let inner = &logged(&sink, "inside the inner task")[0];
+    assert_eq!((inner.task, inner.frame), (Some("inner task"), Some(7)));
+    let back = &logged(&sink, "back in the task")[0];
+    assert_eq!((back.task, back.frame), (Some("context task"), Some(7)));
+
+    let after = &logged(&sink, "after the task")[0];
+    assert_eq!((after.task, after.frame), (None, Some(3)));
+    assert_eq!(&*after.target, module_path!());
+  }
+}
@@ -0,0 +1,323 @@
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use crate::{Level, Record};
+
+/// somewhere log lines go, see add_sink
+pub trait Sink: Send + Sync {
+  fn write(&self, record: &Record);
+  fn flush(&self) {}
+}
+
+/// warnings and errors go to stderr, the rest to stdout
+pub struct StdoutSink;
+
+impl Sink for StdoutSink {
+  fn write(&self, record: &Record) {
+    if record.level >= Level::Warn {
+      eprintln!("{}", record);
+    } else {
+      println!("{}", record);
+    }
+  }
+}
+
+struct FileState {
+  writer: Option<BufWriter<File>>,
+  written: u64,
+}
+
+/// writes to a file, moving it to path.1 once it's grown past max_bytes.
+/// path.1 moves to path.2 and so on, up to the number of old files to keep.
+pub struct FileSink {
+  path: PathBuf,
+  max_bytes: u64,
+  keep: usize,
+  state: Mutex<FileState>,
+}
+
+impl FileSink {
+  /// appends to the file if it's already there
+  pub fn create(path: impl AsRef<Path>, max_bytes: u64, keep: usize) -> std::io::Result<Self> {
+    let path = path.as_ref().to_path_buf();
+    let file = OpenOptions::new().create(true).append(true).open(&path)?;
+    let written = file.metadata()?.len();
+
+    Ok(Self {
+      path,
+      max_bytes,
+      keep,
+      state: Mutex::new(FileState {
+        writer: Some(BufWriter::new(file)),
+        written,
+      }),
+    })
+  }
+
+  fn rotated_path(&self, index: usize) -> PathBuf {
+    let mut path = self.path.clone().into_os_string();
+    path.push(format!(".{}", index));
+    PathBuf::from(path)
+  }
+
+  /// if moving the files fails part way, it carries on appending to the current file,
+  /// and tries again once another max_bytes have been written to it
+  fn rotate(&self, state: &mut FileState) -> std::io::Result<()> {
+    let rotated = self.rotate_files(state);
+    if rotated.is_err() && state.writer.is_none() {
+      let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&self.path)?;
+      state.writer = Some(BufWriter::new(file));
+      state.written = 0;
+    }
+    rotated
+  }
+
+  fn rotate_files(&self, state: &mut FileState) -> std::io::Result<()> {
+    if let Some(mut writer) = state.writer.take() {
+      writer.flush()?;
+    }
+
+    if self.keep == 0 {
+      std::fs::remove_file(&self.path)?;
+    } else {
+      for index in (1..self.keep).rev() {
+        let from = self.rotated_path(index);
+        if from.exists() {
+          std::fs::rename(&from, self.rotated_path(index + 1))?;
+        }
+      }
+      std::fs::rename(&self.path, self.rotated_path(1))?;
+    }
+
+    let file = File::create(&self.path)?;
+    state.writer = Some(BufWriter::new(file));
+    state.written = 0;
+    Ok(())
+  }
+}
+
+impl Sink for FileSink {
+  fn write(&self, record: &Record) {
+    let Ok(mut state) = self.state.lock() else {
+      return;
+    };
+    if state.written >= self.max_bytes
+      && let Err(error) = self.rotate(&mut state)
+    {
+      eprintln!(
+        "failed to rotate log file {}, {}",
+        self.path.display(),
+        error
+      );
+    }
+
+    let line = format!("{}\n", record);
+    let Some(writer) = &mut state.writer else {
+      return;
+    };
+    // errors are what someone goes looking for after a crash, so they're never left in the buffer
+    let written = writer
+      .write_all(line.as_bytes())
+      .and_then(|_| match record.level {
+        Level::Error => writer.flush(),
+        _ => Ok(()),
+      });
+    match written {
+      Ok(()) => state.written += line.len() as u64,
+      Err(error) => {
+        eprintln!(
+          "failed to write log file {}, {}",
+          self.path.display(),
+          error
+        );
+        state.writer = None;
+      }
+    }
+  }
+
+  fn flush(&self) {
+    if let Ok(mut state) = self.state.lock()
+      && let Some(writer) = &mut state.writer
+    {
+      let _ = writer.flush();
+    }
+  }
+}
+
+/// keeps the last lines in memory, for an in game console.
+/// clones share the same buffer, so one can be added as a sink and the other read from.
+#[derive(Clone)]
+pub struct RingBufferSink {
+  records: Arc<Mutex<VecDeque<Record>>>,
+  capacity: usize,
+}
+
+impl RingBufferSink {
+  pub fn new(capacity: usize) -> Self {
+    Self {
+      records: Arc::new(Mutex::new(VecDeque::with_capacity(capacity))),
+      capacity,
+    }
+  }
+
+  /// oldest first
+  pub fn records(&self) -> Vec<Record> {
+    match self.records.lock() {
+      Ok(records) => records.iter().cloned().collect(),
+      Err(_) => Vec::new(),
+    }
+  }
+
+  /// takes every record out of the buffer, oldest first
+  pub fn drain(&self) -> Vec<Record> {
+    match self.records.lock() {
+      Ok(mut records) => records.drain(..).collect(),
+      Err(_) => Vec::new(),
+    }
+  }
+
+  pub fn len(&self) -> usize {
+    self
+      .records
+      .lock()
+      .map(|records| records.len())
+      .unwrap_or(0)
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+}
+
+impl Sink for RingBufferSink {
+  fn write(&self, record: &Record) {
+    let Ok(mut records) = self.records.lock() else {
+      return;
+    };
+    if self.capacity == 0 {
+      return;
+    }
+    if records.len() == self.capacity {
+      records.pop_front();
+    }
+    records.push_back(record.clone());
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::time::SystemTime;
+
+  use super::*;
+
+  fn record(level: Level, message: &str) -> Record {
+    Record {
+      level,
+      target: Arc::from("tests"),
+      task: None,
+      frame: None,
+      time: SystemTime::now(),
+      message: message.to_string(),
+    }
+  }
+
+  fn messages(records: &[Record]) -> Vec<&str> {
+    records
+      .iter()
+      .map(|record| record.message.as_str())
+      .collect()
+  }
+
+  /// an empty directory of its own, so tests running at the same time don't trip over each other
+  fn temp_dir(name: &str) -> PathBuf {
+    let directory = std::env::temp_dir().join(format!("logger-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&directory);
+    std::fs::create_dir_all(&directory).unwrap();
+    directory
+  }
+
+  fn read(path: &Path) -> String {
+    std::fs::read_to_string(path).unwrap()
+  }
+
+  #[test]
+  fn ring_buffer_keeps_the_newest_records() {
+    let sink = RingBufferSink::new(3);
+    let reader = sink.clone();
+    for message in ["one", "two", "three", "four", "five"] {
+      sink.write(&record(Level::Info, message));
+    }
+    assert_eq!(reader.len(), 3);
+    assert_eq!(messages(&reader.records()), ["three", "four", "five"]);
+
+    assert_eq!(messages(&reader.drain()), ["three", "four", "five"]);
+    assert!(sink.is_empty());
+  }
+
+  #[test]
+  fn ring_buffer_with_no_capacity_keeps_nothing() {
+    let sink = RingBufferSink::new(0);
+    sink.write(&record(Level::Error, "lost"));
+    assert!(sink.is_empty());
+  }
+
+  #[test]
+  fn file_sink_rotates_and_keeps_old_files() {
+    let directory = temp_dir("rotate");
+    let path = directory.join("game.log");
+    // every line is past max_bytes, so every write after the first rotates
+    let sink = FileSink::create(&path, 1, 2).unwrap();
+    for message in ["one", "two", "three", "four"] {
+      sink.write(&record(Level::Info, message));
+    }
+    sink.flush();
+
+    assert!(read(&path).contains("four"));
+    assert!(read(&directory.join("game.log.1")).contains("three"));
+    assert!(read(&directory.join("game.log.2")).contains("two"));
+    // "one" fell off the end
+    assert!(!directory.join("game.log.3").exists());
+    std::fs::remove_dir_all(&directory).unwrap();
+  }
+
+  #[test]
+  fn file_sink_appends_to_an_existing_file() {
+    let directory = temp_dir("append");
+    let path = directory.join("game.log");
+    std::fs::write(&path, "from last time\n").unwrap();
+
+    let sink = FileSink::create(&path, 1024, 1).unwrap();
+    sink.write(&record(Level::Warn, "this time"));
+    sink.flush();
+
+    let contents = read(&path);
+    assert!(contents.starts_with("from last time\n"));
+    assert!(contents.contains("warn  tests: this time"));
+    std::fs::remove_dir_all(&directory).unwrap();
+  }
+
+  #[test]
+  fn file_sink_keeps_writing_when_rotating_fails() {
+    let directory = temp_dir("rotate-fails");
+    let path = directory.join("game.log");
+    let sink = FileSink::create(&path, 1, 1).unwrap();
+    sink.write(&record(Level::Info, "one"));
+    sink.flush();
+
+    // nothing to rename, so rotating fails
+    std::fs::remove_file(&path).unwrap();
+    sink.write(&record(Level::Info, "two"));
+    sink.write(&record(Level::Error, "three"));
+
+    // the line went to a fresh file instead of being lost, and that rotated like normal
+    assert!(read(&directory.join("game.log.1")).contains("two"));
+    assert!(read(&path).contains("three"));
+    std::fs::remove_dir_all(&directory).unwrap();
+  }
+}
@@ -5,32 +5,48 @@ fn test_routine(input: RenderRoutineInput) -> RenderRoutineOutput {
   RenderRoutineOutput::Good
 }
 
+/// used when run isn't given the path to a manifest
+const DEFAULT_MANIFEST: &str = include_str!("../manifests/game.toml");
+
+fn task_registry() -> trick::update_manager::manifest::TaskRegistry<trick::renderer::registry::HardwareMessage> {
+  let mut registry = trick::update_manager::manifest::TaskRegistry::new();
+  registry.register_default::<trick::renderer::window::SdlTask>("sdl");
+  registry.register("renderer", |_| {
+    let mut renderer_task = trick::renderer::renderer::RendererTask::default();
+    renderer_task.add_routine(test_routine);
+    Ok(renderer_task)
+  });
+  registry
+}
 
 fn main() -> anyhow::Result<()> {
-  use trick::renderer::registry::HardwareMessage;
-  let mut program = trick::update_manager::UpdateManager::<HardwareMessage>::new()?;
-
-  let sdl_task = renderer::window::SdlTask::default();
-  let mut renderer_task = renderer::renderer::RendererTask::default();
-
-  renderer_task.add_routine(test_routine);
-
-  use trick::*;
-  program.add_task(sdl_task, update_manager::container::TaskPermission::Root)?;
-  program.add_task(
-    renderer_task,
-    update_manager::container::TaskPermission::Root,
-  )?;
-
-  'main: loop {
-    let update_result = program.update_tasks();
-    use trick::update_manager::UpdateReturn;
-    match update_result {
-      UpdateReturn::Ok => {}
-      UpdateReturn::Shutdown => {
-        break 'main;
-      }
-    }
+  use trick::update_manager::manifest::Manifest;
+  let manifest = match std::env::args().nth(1) {
+    Some(manifest_path) => Manifest::load(manifest_path)?,
+    None => Manifest::parse(DEFAULT_MANIFEST)?,
+  };
+  let mut program = manifest.new_manager()?;
+
+  // TRICK_RECORD=session.trickrec cargo run, to replay a session with update_manager::recording
+  if let Ok(recording_path) = std::env::var("TRICK_RECORD") {
+    let recorder = trick::update_manager::recording::Recorder::create(&recording_path)?;
+    program.record_channel(trick::renderer::registry::WINDOW_EVENTS_TOPIC, &recorder);
+  }
+
+  manifest.add_tasks(&task_registry(), &mut program)?;
+
+  let mut engine = trick::engine::Engine::new(program).with_pacing(manifest.pacing());
+  let shutdown_report = engine.run();
+
+  // TRICK_TRACE=trace.json cargo run, then open it in a trace viewer
+  if let Ok(trace_path) = std::env::var("TRICK_TRACE") {
+    engine.manager().profiler().save_chrome_trace(&trace_path)?;
+  }
+  if !shutdown_report.is_clean() {
+    return Err(anyhow::anyhow!(
+      "{} tasks failed to shut down",
+      shutdown_report.failures().count()
+    ));
   }
 
   return Ok(());
@@ -1 +1,302 @@
-pub struct Engine {}
+use std::time::{Duration, Instant};
+
+use crate::update_manager::{UpdateManager, UpdateReturn, shutdown::ShutdownReport};
+
+/// sleeping is only accurate to a millisecond or two depending on the OS,
+/// so the last stretch before a frame is due is spun instead
+pub const DEFAULT_SPIN_MARGIN: Duration = Duration::from_millis(2);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FramePacing {
+  /// frames are updated back to back, as fast as the tasks allow
+  Unlocked,
+  /// frames are started at this rate, waiting out whatever time is left over
+  TargetFps(u32),
+}
+
+/// where the engine gets the time from and how it waits, see Engine::with_clock.
+/// the engine spins on now() for the last spin margin before a frame is due,
+/// so a clock that only moves when it's slept on wants a spin margin of zero.
+pub trait FrameClock: Send {
+  fn now(&self) -> Instant;
+  fn sleep(&self, duration: Duration);
+}
+
+/// the real time, what every engine starts out with
+pub struct SystemClock;
+
+impl FrameClock for SystemClock {
+  fn now(&self) -> Instant {
+    Instant::now()
+  }
+
+  fn sleep(&self, duration: Duration) {
+    std::thread::sleep(duration);
+  }
+}
+
+/// owns the UpdateManager and drives its main loop, pacing the frames.
+/// every task gets the frame delta, index and elapsed time through Task::timestep.
+pub struct Engine<M: Clone + Send + 'static> {
+  manager: UpdateManager<M>,
+  pacing: FramePacing,
+  spin_margin: Duration,
+  clock: Box<dyn FrameClock>,
+  /// when the next frame is due, None until the first frame or while unlocked
+  next_frame: Option<Instant>,
+  /// when the last frame started, the next frame's delta is measured from it
+  last_frame: Option<Instant>,
+}
+
+impl<M: Clone + Send + 'static> Engine<M> {
+  pub fn new(manager: UpdateManager<M>) -> Self {
+    Self {
+      manager,
+      pacing: FramePacing::Unlocked,
+      spin_margin: DEFAULT_SPIN_MARGIN,
+      clock: Box::new(SystemClock),
+      next_frame: None,
+      last_frame: None,
+    }
+  }
+
+  pub fn with_pacing(mut self, pacing: FramePacing) -> Self {
+    self.set_pacing(pacing);
+    self
+  }
+
+  /// measures and waits out frames with the clock instead of the real time (eg: in tests)
+  pub fn with_clock(mut self, clock: impl FrameClock + 'static) -> Self {
+    self.clock = Box::new(clock);
+    self.next_frame = None;
+    self.last_frame = None;
+    self
+  }
+
+  pub fn pacing(&self) -> FramePacing {
+    self.pacing
+  }
+
+  pub fn set_pacing(&mut self, pacing: FramePacing) {
+    self.pacing = pacing;
+    self.next_frame = None;
+  }
+
+  /// how long before a frame is due the engine stops sleeping and starts spinning.
+  /// zero only sleeps, which is cheaper on the cpu but less precise.
+  pub fn set_spin_margin(&mut self, spin_margin: Duration) {
+    self.spin_margin = spin_margin;
+  }
+
+  pub fn manager(&self) -> &UpdateManager<M> {
+    &self.manager
+  }
+
+  pub fn manager_mut(&mut self) -> &mut UpdateManager<M> {
+    &mut self.manager
+  }
+
+  /// hands the manager back, without shutting it down
+  pub fn into_manager(self) -> UpdateManager<M> {
+    self.manager
+  }
+
+  /// waits until the next frame is due, then updates every task once.
+  /// the first frame's delta is zero, like UpdateManager::update_tasks.
+  pub fn run_frame(&mut self) -> UpdateReturn {
+    self.wait_for_frame();
+    let now = self.clock.now();
+    let delta = match self.last_frame {
+      Some(last_frame) => now - last_frame,
+      None => Duration::ZERO,
+    };
+    self.last_frame = Some(now);
+    self.manager.update_tasks_with_delta(delta)
+  }
+
+  /// updates frames until a task asks for a shutdown, then shuts the manager down
+  pub fn run(&mut self) -> ShutdownReport {
+    while let UpdateReturn::Ok = self.run_frame() {}
+    self.manager.shutdown()
+  }
+
+  fn frame_length(&self) -> Option<Duration> {
+    match self.pacing {
+      FramePacing::Unlocked => None,
+      FramePacing::TargetFps(fps) => Some(Duration::from_secs(1) / fps.max(1)),
+    }
+  }
+
+  /// sleeps for most of the time left, and spins for the rest
+  fn wait_for_frame(&mut self) {
+    let Some(frame_length) = self.frame_length() else {
+      return;
+    };
+
+    let now = self.clock.now();
+    let Some(due) = self.next_frame else {
+      self.next_frame = Some(now + frame_length);
+      return;
+    };
+
+    if due > now {
+      let sleep_for = (due - now).saturating_sub(self.spin_margin);
+      if !sleep_for.is_zero() {
+        self.clock.sleep(sleep_for);
+      }
+      while self.clock.now() < due {
+        std::hint::spin_loop();
+      }
+    }
+
+    // frames are scheduled from when they were due, so the rate doesn't drift.
+    // a frame that ran over by more than a whole frame starts the schedule over,
+    // instead of rushing through the missed frames.
+    let next_frame = due + frame_length;
+    let now = self.clock.now();
+    self.next_frame = Some(if next_frame < now {
+      now + frame_length
+    } else {
+      next_frame
+    });
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::{Arc, Mutex};
+
+  use super::*;
+  use crate::update_manager::{
+    PostInit, Task, TaskResult, channel::ChannelRegistry, container::TaskPermission,
+    timestep::TimeStep,
+  };
+
+  /// only moves when it's slept on, or when a task does some "work"
+  #[derive(Clone)]
+  struct FakeClock {
+    now: Arc<Mutex<Instant>>,
+    sleeps: Arc<Mutex<Vec<Duration>>>,
+  }
+
+  impl FakeClock {
+    fn new() -> Self {
+      Self {
+        now: Arc::new(Mutex::new(Instant::now())),
+        sleeps: Arc::new(Mutex::new(Vec::new())),
+      }
+    }
+
+    fn advance(&self, duration: Duration) {
+      *self.now.lock().unwrap() += duration;
+    }
+
+    fn sleeps(&self) -> Vec<Duration> {
+      self.sleeps.lock().unwrap().clone()
+    }
+  }
+
+  impl FrameClock for FakeClock {
+    fn now(&self) -> Instant {
+      *self.now.lock().unwrap()
+    }
+
+    fn sleep(&self, duration: Duration) {
+      self.sleeps.lock().unwrap().push(duration);
+      self.advance(duration);
+    }
+  }
+
+  /// takes the next amount of work every update, and writes down every delta it's given.
+  /// asks for a shutdown once it runs out of work.
+  struct Worker {
+    clock: FakeClock,
+    work: Vec<Duration>,
+    deltas: Arc<Mutex<Vec<Duration>>>,
+  }
+
+  impl Task<u32> for Worker {
+    fn start(&mut self, _: ChannelRegistry<u32>) -> anyhow::Result<PostInit> {
+      Ok(PostInit {
+        name: "worker",
+        tags: &[],
+        requests: &[],
+      })
+    }
+
+    fn timestep(&mut self, timestep: &TimeStep) {
+      self.deltas.lock().unwrap().push(timestep.delta);
+    }
+
+    fn update(&mut self) -> TaskResult {
+      if self.work.is_empty() {
+        return TaskResult::RequestShutdown;
+      }
+      self.clock.advance(self.work.remove(0));
+      TaskResult::Ok
+    }
+
+    fn end(&mut self) -> anyhow::Result<()> {
+      Ok(())
+    }
+  }
+
+  fn ms(millis: u64) -> Duration {
+    Duration::from_millis(millis)
+  }
+
+  /// runs the engine until the worker is out of work, returning the deltas and sleeps
+  fn run(pacing: FramePacing, work: &[u64]) -> (Vec<Duration>, Vec<Duration>) {
+    let clock = FakeClock::new();
+    let deltas = Arc::new(Mutex::new(Vec::new()));
+    let mut manager = UpdateManager::new().unwrap();
+    let worker = Worker {
+      clock: clock.clone(),
+      work: work.iter().copied().map(ms).collect(),
+      deltas: deltas.clone(),
+    };
+    manager.add_task(worker, TaskPermission::Root).unwrap();
+
+    let mut engine = Engine::new(manager)
+      .with_pacing(pacing)
+      .with_clock(clock.clone());
+    // the fake clock only moves when it's slept on, so there's nothing to spin on
+    engine.set_spin_margin(Duration::ZERO);
+    engine.run();
+
+    // one more frame than there's work, for the one that asked to shut down
+    assert_eq!(engine.manager().frame_index(), work.len() as u64 + 1);
+    let deltas = deltas.lock().unwrap().clone();
+    (deltas, clock.sleeps())
+  }
+
+  #[test]
+  fn target_fps_waits_out_the_rest_of_the_frame() {
+    let (deltas, sleeps) = run(FramePacing::TargetFps(100), &[3, 3, 3]);
+    assert_eq!(deltas, [ms(0), ms(10), ms(10), ms(10)]);
+    assert_eq!(sleeps, [ms(7), ms(7), ms(7)]);
+  }
+
+  #[test]
+  fn a_long_frame_starts_the_schedule_over() {
+    let (deltas, sleeps) = run(FramePacing::TargetFps(100), &[3, 25, 3]);
+    // the long frame isn't caught up on with short ones, the next is a whole frame later
+    assert_eq!(deltas, [ms(0), ms(10), ms(25), ms(10)]);
+    assert_eq!(sleeps, [ms(7), ms(7)]);
+  }
+
+  #[test]
+  fn a_slightly_late_frame_keeps_the_schedule() {
+    let (deltas, sleeps) = run(FramePacing::TargetFps(100), &[3, 14, 3]);
+    // due at 20ms, started at 24ms, and the next is still due at 30ms
+    assert_eq!(deltas, [ms(0), ms(10), ms(14), ms(6)]);
+    assert_eq!(sleeps, [ms(7), ms(3)]);
+  }
+
+  #[test]
+  fn unlocked_frames_run_back_to_back() {
+    let (deltas, sleeps) = run(FramePacing::Unlocked, &[3, 5, 4]);
+    assert_eq!(deltas, [ms(0), ms(3), ms(5), ms(4)]);
+    assert!(sleeps.is_empty());
+  }
+}
@@ -1,9 +1,89 @@
 use crate::update_manager::channel::TaskReceiver;
+use crate::update_manager::codec::{self, ByteReader, MessageCodec};
+use crate::update_manager::rpc::RpcMessage;
+
+/// topic the sdl task publishes WindowEvents to, see ChannelRegistry::subscribe
+pub const WINDOW_EVENTS_TOPIC: &str = "window events";
 
 #[derive(Clone)]
 pub enum HardwareMessage {
-  RequestRawWindowHandle,
-  RenderSyncro(SyncRawWindow),
+  Window(WindowEvent),
+}
+
+/// what the renderer asks of the sdl task, over an rpc on RENDERER_CHANNEL
+pub enum WindowRequest {
+  RawWindowHandle,
+}
+
+pub enum WindowResponse {
+  RawWindow(SyncRawWindow),
+  /// the window isn't open, or sdl couldn't hand out its handles
+  Unavailable,
+}
+
+/// what goes over RENDERER_CHANNEL, linked with TaskRequest::LinkTypedChannel
+pub type WindowRpc = RpcMessage<WindowRequest, WindowResponse>;
+
+#[derive(Clone)]
+pub enum WindowEvent {
+  Resized(SurfaceResolution),
+  FocusGained,
+  FocusLost,
+  /// key names come from sdl, eg: "A", "Space", "Left Shift"
+  KeyDown {
+    key: String,
+    repeat: bool,
+  },
+  KeyUp {
+    key: String,
+  },
+}
+
+// so hardware and input can be recorded and replayed, see update_manager::recording
+impl MessageCodec for HardwareMessage {
+  fn encode(&self, out: &mut Vec<u8>) -> anyhow::Result<()> {
+    let HardwareMessage::Window(window_event) = self;
+    match window_event {
+      WindowEvent::Resized(resolution) => {
+        codec::put_u8(out, 0);
+        codec::put_u32(out, resolution.width);
+        codec::put_u32(out, resolution.height);
+      }
+      WindowEvent::FocusGained => codec::put_u8(out, 1),
+      WindowEvent::FocusLost => codec::put_u8(out, 2),
+      WindowEvent::KeyDown { key, repeat } => {
+        codec::put_u8(out, 3);
+        codec::put_str(out, key);
+        codec::put_u8(out, *repeat as u8);
+      }
+      WindowEvent::KeyUp { key } => {
+        codec::put_u8(out, 4);
+        codec::put_str(out, key);
+      }
+    }
+    Ok(())
+  }
+
+  fn decode(bytes: &[u8]) -> anyhow::Result<Self> {
+    let mut reader = ByteReader::new(bytes);
+    let window_event = match reader.u8()? {
+      0 => WindowEvent::Resized(SurfaceResolution {
+        width: reader.u32()?,
+        height: reader.u32()?,
+      }),
+      1 => WindowEvent::FocusGained,
+      2 => WindowEvent::FocusLost,
+      3 => WindowEvent::KeyDown {
+        key: reader.str()?.to_string(),
+        repeat: reader.u8()? != 0,
+      },
+      4 => WindowEvent::KeyUp {
+        key: reader.str()?.to_string(),
+      },
+      kind => anyhow::bail!("unknown window event {}", kind),
+    };
+    Ok(HardwareMessage::Window(window_event))
+  }
 }
 
 #[derive(Clone, Copy)]
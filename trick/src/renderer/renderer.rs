@@ -1,19 +1,31 @@
 use std::sync::Arc;
+use std::time::Duration;
 
 use crate::{
   renderer::{
-    registry::{HardwareMessage, SurfaceChanges, SyncRawWindow},
+    registry::{
+      HardwareMessage, SurfaceChanges, SyncRawWindow, WindowRequest, WindowResponse, WindowRpc,
+    },
     shaders::PipelineManager,
   },
   update_manager::{
-    PostInit, Task, TaskResult, TaskTag,
-    channel::{self, TaskReceiver},
+    PostInit, Task, TaskRequest, TaskResult, TaskTag,
+    channel::{self, AnyChannel, TaskReceiver, TypedLink},
+    rpc::{PendingReply, RpcClient},
   },
 };
 
 /// **************************************** CONSTANTS ****************************************** ///
 pub const RENDERER_CHANNEL: &'static str = "IPEPIFSUIHDFIUHSIHGIHSFUIGHIYWHWRURUURURURURUUR"; // computers don't need clarity
 const RENDERER_TAGS: &'static [TaskTag] = &[];
+// the renderer reads whatever state the window and input tasks left behind this frame
+const RENDERER_REQUESTS: &'static [TaskRequest] = &[
+  TaskRequest::RunAfter(crate::renderer::window::SDL_TASK_LABEL),
+  // the sdl task answers requests for the window on this
+  TaskRequest::LinkTypedChannel(TypedLink::of::<WindowRpc>(RENDERER_CHANNEL)),
+];
+// asks the sdl task for the window again if it hasn't answered by then
+const WINDOW_REQUEST_TIMEOUT: Duration = Duration::from_secs(1);
 
 use crate::task_routine::TaskRoutine;
 
@@ -46,28 +58,8 @@ impl TaskRoutine for RendererTask {
 pub struct RendererTask {
   routines: Vec<<RendererTask as TaskRoutine>::RoutineFn>,
   wgpu: Option<WgpuRenderer>,
-  channel_registry: Option<channel::ChannelRegistry<HardwareMessage>>,
-  renderer_channel: Option<channel::TaskChannel<HardwareMessage>>,
-}
-
-impl RendererTask {
-  fn sync_renderer_channel<'a>(
-    &'a mut self,
-  ) -> &'a mut Option<channel::TaskChannel<HardwareMessage>> {
-    if let Some(_renderer_channel) = &mut self.renderer_channel {
-      return &mut self.renderer_channel;
-    }
-
-    if let Some(channel_registry) = &self.channel_registry {
-      let channel_request = channel_registry.get_or_create(RENDERER_CHANNEL);
-
-      if let Some(channel_accepted) = channel_request {
-        self.renderer_channel = Some(channel_accepted);
-      }
-    }
-
-    return &mut self.renderer_channel;
-  }
+  window_client: Option<RpcClient<WindowRequest, WindowResponse>>,
+  window_reply: Option<PendingReply<WindowRequest, WindowResponse>>,
 }
 
 impl Default for RendererTask {
@@ -75,8 +67,29 @@ impl Default for RendererTask {
     Self {
       routines: Vec::new(),
       wgpu: None,
-      channel_registry: None,
-      renderer_channel: None,
+      window_client: None,
+      window_reply: None,
+    }
+  }
+}
+
+impl RendererTask {
+  /// asks the sdl task for its window, None until it has answered with one
+  fn poll_window(&mut self) -> Option<SyncRawWindow> {
+    let window_client = self.window_client.as_ref()?;
+
+    if self.window_reply.is_none() {
+      self.window_reply = window_client
+        .call(WindowRequest::RawWindowHandle, WINDOW_REQUEST_TIMEOUT)
+        .ok();
+    }
+
+    let reply = self.window_reply.as_mut()?.try_reply()?;
+    // answered or timed out, either way the next frame asks again if it has to
+    self.window_reply = None;
+    match reply {
+      Ok(WindowResponse::RawWindow(raw_window)) => Some(raw_window),
+      _ => None,
     }
   }
 }
@@ -84,43 +97,32 @@ impl Default for RendererTask {
 impl Task<HardwareMessage> for RendererTask {
   fn start(
     &mut self,
-    channel_registry: channel::ChannelRegistry<HardwareMessage>,
+    _channel_registry: channel::ChannelRegistry<HardwareMessage>,
   ) -> anyhow::Result<PostInit> {
-    self.channel_registry = Some(channel_registry);
-
     Ok(PostInit {
       tags: RENDERER_TAGS,
       name: "renderer task",
-      requests: &[],
+      requests: RENDERER_REQUESTS,
     })
   }
 
+  fn typed_channel_linked(&mut self, id: &'static str, channel: AnyChannel) {
+    if id == RENDERER_CHANNEL
+      && let Ok(channel) = channel.downcast()
+    {
+      self.window_client = Some(RpcClient::new(channel));
+      self.window_reply = None;
+    }
+  }
+
   fn update(&mut self) -> TaskResult {
 
     self.run_routines();
 
-    let is_wgpu_initialised = self.wgpu.is_none();
-    let mut new_wgpu = None;
-
-    if let Some(channel) = self.sync_renderer_channel() {
-      if is_wgpu_initialised {
-        channel
-          .send(HardwareMessage::RequestRawWindowHandle)
-          .unwrap();
-      }
-
-      while let Some(message) = channel.try_recv() {
-        match message {
-          HardwareMessage::RenderSyncro(raw_window) => {
-            new_wgpu = WgpuRenderer::new(raw_window).ok();
-          }
-          _ => {}
-        }
-      }
-    }
-
-    if new_wgpu.is_some() {
-      self.wgpu = new_wgpu;
+    if self.wgpu.is_none()
+      && let Some(raw_window) = self.poll_window()
+    {
+      self.wgpu = WgpuRenderer::new(raw_window).ok();
     }
 
     if let Some(renderer) = &mut self.wgpu {
@@ -135,6 +137,7 @@ impl Task<HardwareMessage> for RendererTask {
 
   fn end(&mut self) -> anyhow::Result<()> {
     self.wgpu = None;
+    self.window_reply = None;
     Ok(())
   }
 }
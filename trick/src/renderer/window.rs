@@ -1,65 +1,62 @@
 use crate::{
-  renderer::registry::{HardwareMessage, SurfaceChanges, SurfaceResolution, SyncRawWindow},
+  renderer::registry::{
+    HardwareMessage, SurfaceChanges, SurfaceResolution, SyncRawWindow, WINDOW_EVENTS_TOPIC,
+    WindowEvent, WindowRequest, WindowResponse, WindowRpc,
+  },
   update_manager::{
-    self, Task, TaskResult, TaskTag,
-    channel::{self, TaskChannel, TaskSender},
+    self, Task, TaskRequest, TaskResult, TaskTag,
+    channel::{self, AnyChannel, TaskChannel, TaskSender, TopicPublisher, TypedLink},
+    rpc::RpcServer,
   },
 };
 
 // contains the unsafe impl as much as possible by putting it in this module
 
+pub const SDL_TASK_LABEL: &'static str = "sdl3 desktop task";
+// the renderer asks for the window over this
+const SDL_REQUESTS: &'static [TaskRequest] =
+  &[TaskRequest::LinkTypedChannel(TypedLink::of::<WindowRpc>(
+    crate::renderer::renderer::RENDERER_CHANNEL,
+  ))];
+
 pub struct SdlTask {
   handle: Option<SdlHandle>,
-  channel_reg: Option<channel::ChannelRegistry<HardwareMessage>>,
-  renderer_channel: Option<channel::TaskChannel<HardwareMessage>>,
+  window_server: Option<RpcServer<WindowRequest, WindowResponse>>,
+  window_events: Option<TopicPublisher<HardwareMessage>>,
 }
 
 impl Default for SdlTask {
   fn default() -> Self {
     Self {
       handle: None,
-      channel_reg: None,
-      renderer_channel: None,
+      window_server: None,
+      window_events: None,
     }
   }
 }
 
-impl SdlTask {
-  fn sync_renderer_channel<'a>(
-    &'a mut self,
-  ) -> &'a mut Option<channel::TaskChannel<HardwareMessage>> {
-    if let Some(_renderer_channel) = &mut self.renderer_channel {
-      return &mut self.renderer_channel;
-    }
-
-    if let Some(channel_registry) = self.channel_reg.clone() {
-      let channel_request =
-        channel_registry.get_or_create(crate::renderer::renderer::RENDERER_CHANNEL);
-
-      if let Some(channel_accepted) = channel_request {
-        self.renderer_channel = Some(channel_accepted);
-      }
-    }
-
-    return &mut self.renderer_channel;
-  }
-}
-
 impl Task<HardwareMessage> for SdlTask {
   fn start(
     &mut self,
     channel_registry: channel::ChannelRegistry<HardwareMessage>,
   ) -> anyhow::Result<update_manager::PostInit> {
     self.handle = Some(SdlHandle::new()?);
-    self.channel_reg = Some(channel_registry);
-    let _ = self.sync_renderer_channel();
+    self.window_events = channel_registry.publisher(WINDOW_EVENTS_TOPIC);
     return Ok(update_manager::PostInit {
-      name: "sdl3 desktop task",
-      tags: &[TaskTag::DropLast],
-      requests: &[],
+      name: SDL_TASK_LABEL,
+      tags: &[TaskTag::DropLast, TaskTag::MainThread],
+      requests: SDL_REQUESTS,
     });
   }
 
+  fn typed_channel_linked(&mut self, id: &'static str, channel: AnyChannel) {
+    if id == crate::renderer::renderer::RENDERER_CHANNEL
+      && let Ok(channel) = channel.downcast()
+    {
+      self.window_server = Some(RpcServer::new(channel));
+    }
+  }
+
   fn end(&mut self) -> anyhow::Result<()> {
     // set to none, dropping everything
     self.handle = None;
@@ -67,36 +64,27 @@ impl Task<HardwareMessage> for SdlTask {
   }
 
   fn update(&mut self) -> TaskResult {
-    // i know this is terrible, but i just want my stupid code to work, and daddy borrow checker says no.
-    let mut raw_window = None;
-    if let Some(sdl_handle) = &mut self.handle {
-      raw_window = Some(
-        sdl_handle
-          .get_handles()
-          .expect("failed to get raw window handle"),
-      );
-    }
-
-    // recieve updates from the renderer channel
-    if let Some(renderer_channel) = self.sync_renderer_channel() {
-      while let Some(message) = renderer_channel.try_recv() {
-        match message {
-          HardwareMessage::RequestRawWindowHandle => {
-            if let Some(ref window_handle) = raw_window {
-              renderer_channel
-                .send(HardwareMessage::RenderSyncro(window_handle.clone()))
-                .expect("FAILED TO SEND MESSAGE");
-            }
-          }
-          _ => {}
-        }
-      }
+    // answer the renderer
+    if let Some(window_server) = &self.window_server {
+      let handle = &mut self.handle;
+      window_server.serve(|request| match request {
+        WindowRequest::RawWindowHandle => match handle.as_mut().map(SdlHandle::get_handles) {
+          Some(Ok(raw_window)) => WindowResponse::RawWindow(raw_window),
+          _ => WindowResponse::Unavailable,
+        },
+      });
     }
 
     // sdl handle mutex scope START
     {
       let sdl_handle = { self.handle.as_mut().unwrap() };
 
+      let publish = |window_event: WindowEvent| {
+        if let Some(window_events) = &self.window_events {
+          window_events.publish(HardwareMessage::Window(window_event));
+        }
+      };
+
       for event in sdl_handle.event_pump.poll_iter() {
         match event {
           sdl3::event::Event::Quit { .. } => {
@@ -104,21 +92,39 @@ impl Task<HardwareMessage> for SdlTask {
           }
           sdl3::event::Event::Window { win_event, .. } => match win_event {
             sdl3::event::WindowEvent::Resized(..) => {
+              // poll_iter is still borrowing the handle, so only the window is read here
+              let window_resolution = {
+                let size = sdl_handle.sdl_window.size();
+                SurfaceResolution {
+                  width: size.0,
+                  height: size.1,
+                }
+              };
               if let Some(sender) = &sdl_handle.renderer_channel {
-                let window_resolution = {
-                  let size = sdl_handle.sdl_window.size();
-                  SurfaceResolution {
-                    width: size.0,
-                    height: size.1,
-                  }
-                };
                 sender
                   .send(SurfaceChanges::UpdateResolution(window_resolution))
                   .unwrap();
               }
+              publish(WindowEvent::Resized(window_resolution));
             }
+            sdl3::event::WindowEvent::FocusGained => publish(WindowEvent::FocusGained),
+            sdl3::event::WindowEvent::FocusLost => publish(WindowEvent::FocusLost),
             _ => {}
           },
+          sdl3::event::Event::KeyDown {
+            keycode: Some(keycode),
+            repeat,
+            ..
+          } => publish(WindowEvent::KeyDown {
+            key: keycode.name(),
+            repeat,
+          }),
+          sdl3::event::Event::KeyUp {
+            keycode: Some(keycode),
+            ..
+          } => publish(WindowEvent::KeyUp {
+            key: keycode.name(),
+          }),
           _ => {}
         }
       }
@@ -1,27 +1,78 @@
-use crate::update_manager::container::TaskPermission;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
 
+use crate::update_manager::{
+  channel::{TaskChannel, TaskReceiver, TaskSender},
+  container::{DeniedAction, PermissionDenied, TaskId},
+  control::{ManagerHandle, ManagerMessage, TaskInfo, TimeControl},
+};
+
+pub mod async_task;
+pub mod bridge;
 pub mod channel;
+pub mod codec;
 pub mod container;
+pub mod control;
+pub mod error;
+pub mod harness;
+pub mod hot_reload;
+pub mod manifest;
+pub mod ordering;
+pub mod profiler;
+pub mod recording;
+pub mod rpc;
+pub mod scheduler;
+pub mod shutdown;
+pub mod snapshot;
+pub mod supervisor;
+pub mod time;
+pub mod timestep;
+pub mod watchdog;
 
+#[derive(Clone, Debug, PartialEq)]
 pub enum TaskResult {
   /// system error: the entire program or task needs to go down.
-  ErrFatal(&'static str),
+  ErrFatal(error::TaskError),
   /// logic error: just restart the task.
   ErrReload,
+  /// the task panicked, or an earlier panic left it half done. restarted like ErrFatal.
+  ErrPanicked(String),
   /// success: nothing of note to send
   Ok,
   /// request shutdown of the entire program
   RequestShutdown,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum TaskRequest {
-  /// link to channel with ID
+  /// link to channel with ID, the manager hands it over through Task::channel_linked
   LinkChannel(&'static str),
+  /// same as LinkChannel, for a channel carrying its own payload type instead of M
+  /// (see ChannelRegistry::get_or_create_typed). handed over through Task::typed_channel_linked.
+  LinkTypedChannel(channel::TypedLink),
+  /// update this task before the task with the given label.
+  /// labels that don't belong to any task are ignored, since the task may be added later.
+  RunBefore(&'static str),
+  /// update this task after the task with the given label.
+  RunAfter(&'static str),
+  /// get a ManagerHandle through Task::manager_linked, to add and remove tasks while running
+  ManagerControl,
 }
 
-#[derive(PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum TaskTag {
   DropLast,
+  /// the task has to be updated from the thread that owns the UpdateManager,
+  /// even when the manager has a worker pool. (eg: sdl, which is bound to the main thread)
+  MainThread,
+  /// update at a fixed rate in hz, running extra steps to catch up on slow frames.
+  /// tasks without this tag are updated once per frame.
+  FixedRate(u32),
+  /// how the task is restarted when it fails, tasks without it use RestartPolicy::DEFAULT
+  Restart(supervisor::RestartPolicy),
+  /// how long one call into the task can take before the watchdog reports it as hung,
+  /// tasks without it use watchdog::DEFAULT_TIME_BUDGET
+  TimeBudget(Duration),
 }
 
 pub struct PostInit {
@@ -30,16 +81,69 @@ pub struct PostInit {
   pub requests: &'static [TaskRequest],
 }
 
-pub enum ManagerMessage {
-  TaskChannel(),
-}
-
-pub trait Task<M: Clone + Send + 'static> {
+/// tasks are Send, so the UpdateManager can hand them to its worker threads.
+pub trait Task<M: Clone + Send + 'static>: Send {
   fn start(&mut self, channel_registry: channel::ChannelRegistry<M>) -> anyhow::Result<PostInit>;
   fn update(&mut self) -> TaskResult;
   fn end(&mut self) -> anyhow::Result<()>;
+
+  /// called right before every update, with the time since the last one.
+  fn timestep(&mut self, _timestep: &timestep::TimeStep) {}
+
+  /// called once the channel from a TaskRequest::LinkChannel is linked,
+  /// and again with the same channel after the task is reloaded.
+  /// messages sent before the other side asks for the ID are queued until it does.
+  fn channel_linked(&mut self, _id: &'static str, _channel: channel::TaskChannel<M>) {}
+
+  /// same as channel_linked, for a TaskRequest::LinkTypedChannel.
+  /// AnyChannel::downcast gets the TaskChannel back out.
+  fn typed_channel_linked(&mut self, _id: &'static str, _channel: channel::AnyChannel) {}
+
+  /// called after start for tasks with a TaskRequest::ManagerControl, and again after a reload.
+  fn manager_linked(&mut self, _manager: ManagerHandle<M>) {}
+
+  /// tasks that want to keep their state through a reload return their snapshot::Snapshot here.
+  fn snapshot(&mut self) -> Option<&mut dyn snapshot::Snapshot> {
+    None
+  }
 }
 
+/// so tasks created at runtime (eg: spawned through a ManagerHandle) can be added like any other
+impl<M: Clone + Send + 'static, TaskT: Task<M> + ?Sized> Task<M> for Box<TaskT> {
+  fn start(&mut self, channel_registry: channel::ChannelRegistry<M>) -> anyhow::Result<PostInit> {
+    (**self).start(channel_registry)
+  }
+
+  fn update(&mut self) -> TaskResult {
+    (**self).update()
+  }
+
+  fn end(&mut self) -> anyhow::Result<()> {
+    (**self).end()
+  }
+
+  fn timestep(&mut self, timestep: &timestep::TimeStep) {
+    (**self).timestep(timestep)
+  }
+
+  fn channel_linked(&mut self, id: &'static str, channel: channel::TaskChannel<M>) {
+    (**self).channel_linked(id, channel)
+  }
+
+  fn typed_channel_linked(&mut self, id: &'static str, channel: channel::AnyChannel) {
+    (**self).typed_channel_linked(id, channel)
+  }
+
+  fn manager_linked(&mut self, manager: ManagerHandle<M>) {
+    (**self).manager_linked(manager)
+  }
+
+  fn snapshot(&mut self) -> Option<&mut dyn snapshot::Snapshot> {
+    (**self).snapshot()
+  }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum UpdateReturn {
   Ok,
   Shutdown,
@@ -48,57 +152,657 @@ pub enum UpdateReturn {
 pub struct UpdateManager<M: Clone + Send + 'static> {
   tasks: Vec<container::TaskContainer<M>>,
   hardware_registry: channel::ChannelRegistry<M>,
+  workers: Option<scheduler::WorkerPool<M>>,
+  /// indices into tasks, sorted by dependency. see ordering::sort_tasks
+  stages: Vec<Vec<usize>>,
+  last_update: Option<Instant>,
+  /// time spent updating, advanced by every frame's unscaled delta
+  clock: Duration,
+  /// scaled time, see UpdateManager::time
+  time: time::TimeService,
+  /// frames updated so far, see TimeStep::frame
+  frame_index: u64,
+  restart_log: VecDeque<supervisor::RestartRecord>,
+  next_task_id: u64,
+  denied_sender: TaskSender<PermissionDenied>,
+  denied_receiver: TaskReceiver<PermissionDenied>,
+  denied_log: VecDeque<PermissionDenied>,
+  control_sender: TaskSender<(TaskId, ManagerMessage<M>)>,
+  control_receiver: TaskReceiver<(TaskId, ManagerMessage<M>)>,
+  shutdown_timeout: Duration,
+  profiler: profiler::Profiler,
+  /// what every task returned during the last frame, in update order
+  frame_results: Vec<(TaskId, TaskResult)>,
+  /// see UpdateManager::record_channel
+  recorder: Option<recording::Recorder>,
+  watchdog: watchdog::Watchdog,
+  hang_receiver: TaskReceiver<watchdog::HangReport>,
+  hang_log: VecDeque<watchdog::HangReport>,
+  /// see UpdateManager::subscribe_errors
+  error_subscribers: Vec<TaskSender<error::TaskError>>,
 }
 
+/// the oldest restart and denied action records are thrown away past this
+const RECORD_LOG_LENGTH: usize = 256;
+
 impl<M: Clone + Send + 'static> Drop for UpdateManager<M> {
   fn drop(&mut self) {
-    // remove everything that doesn't have the "DropLast" tag
-    self
-      .tasks
-      .retain(|x| x.get_tag().contains(&TaskTag::DropLast));
+    // nothing left if shutdown was already called
+    if !self.tasks.is_empty() {
+      self.shutdown();
+    }
   }
 }
 
 impl<M: Clone + Send + 'static> UpdateManager<M> {
   pub fn new() -> anyhow::Result<Self> {
+    let (denied_sender, denied_receiver) = TaskChannel::new().split();
+    let (control_sender, control_receiver) = TaskChannel::new().split();
+    let (watchdog, hang_receiver) = watchdog::Watchdog::new()?;
     Ok(Self {
       tasks: Vec::new(),
       hardware_registry: channel::ChannelRegistry::new(),
+      workers: None,
+      stages: Vec::new(),
+      last_update: None,
+      clock: Duration::ZERO,
+      time: time::TimeService::new(),
+      frame_index: 0,
+      restart_log: VecDeque::new(),
+      next_task_id: 0,
+      denied_sender,
+      denied_receiver,
+      denied_log: VecDeque::new(),
+      control_sender,
+      control_receiver,
+      shutdown_timeout: shutdown::DEFAULT_SHUTDOWN_TIMEOUT,
+      profiler: profiler::Profiler::new(),
+      frame_results: Vec::new(),
+      recorder: None,
+      watchdog,
+      hang_receiver,
+      hang_log: VecDeque::new(),
+      error_subscribers: Vec::new(),
     })
   }
 
+  /// tasks without the MainThread tag are run in parallel on a pool of worker threads,
+  /// while the main thread tasks are run on the thread calling update_tasks.
+  pub fn new_multithreaded(worker_count: usize) -> anyhow::Result<Self> {
+    let mut manager = Self::new()?;
+    manager.workers = Some(scheduler::WorkerPool::new(worker_count)?);
+    Ok(manager)
+  }
+
+  /// how long one call into a task can take before the watchdog reports it,
+  /// for tasks without a TaskTag::TimeBudget
+  pub fn set_time_budget(&mut self, budget: Duration) {
+    self.watchdog.set_default_budget(budget);
+  }
+
+  /// every call into a task that ran past its time budget, oldest first
+  pub fn hang_log(&mut self) -> impl Iterator<Item = &watchdog::HangReport> {
+    self.collect_hang_reports();
+    self.hang_log.iter()
+  }
+
+  /// every task failure from now on, as it happens: failed starts and restarts,
+  /// fatal errors, reload requests and panics. (eg: for an editor's error list)
+  /// the restart log only keeps the message, this keeps the whole error.
+  pub fn subscribe_errors(&mut self) -> TaskReceiver<error::TaskError> {
+    let (sender, receiver) = TaskChannel::new().split();
+    self.error_subscribers.push(sender);
+    receiver
+  }
+
+  /// dropped receivers are forgotten about
+  fn publish_error(&mut self, error: error::TaskError) {
+    self.error_subscribers.retain(|subscriber| {
+      !subscriber.is_disconnected() && subscriber.send(error.clone()).is_ok()
+    });
+  }
+
+  /// how long each task's end() gets during shutdown.
+  /// main thread tasks are ended on the calling thread, and can't be timed out.
+  pub fn set_shutdown_timeout(&mut self, timeout: Duration) {
+    self.shutdown_timeout = timeout;
+  }
+
+  /// ends every task in reverse dependency order, with the DropLast tasks last,
+  /// dropping each task as soon as it has ended. the manager is empty afterwards.
+  /// called when the manager is dropped, if it hasn't been called already.
+  pub fn shutdown(&mut self) -> shutdown::ShutdownReport {
+    let order = shutdown::shutdown_order(&self.tasks, &self.stages);
+    let mut tasks: Vec<Option<container::TaskContainer<M>>> =
+      self.tasks.drain(..).map(Some).collect();
+    self.stages.clear();
+
+    let mut report = shutdown::ShutdownReport::default();
+    for index in order {
+      let Some(task) = tasks[index].take() else {
+        continue;
+      };
+
+      let (outcome, mut span) =
+        profiler::measure(self.profiler.epoch(), profiler::TaskPhase::End, || {
+          shutdown::end_task(&task, self.shutdown_timeout)
+        });
+      span.task = task.get_label();
+      self.profiler.record(span);
+      match &outcome {
+        shutdown::ShutdownOutcome::Failed(error) => {
+          logger::error!("task {} failed to end: {}", task.get_label(), error);
+        }
+        shutdown::ShutdownOutcome::TimedOut => {
+          logger::warn!(
+            "task {} didn't end within {:?}, leaving it behind",
+            task.get_label(),
+            self.shutdown_timeout
+          );
+        }
+        _ => {}
+      }
+
+      report.tasks.push(shutdown::TaskShutdown {
+        id: task.get_id(),
+        label: task.get_label(),
+        outcome,
+      });
+      drop(task);
+    }
+
+    // messages sent while ending are part of the recording too
+    if let Some(recorder) = &self.recorder
+      && let Err(error) = recorder.flush()
+    {
+      logger::error!("failed to flush recording, {}", error);
+    }
+    logger::set_frame(None);
+    logger::flush();
+
+    report
+  }
+
+  /// what every task that updated returned during the last frame, in update order.
+  /// tasks that didn't update (stopped, restarting, or a fixed rate task with no step due) aren't in it.
+  pub fn frame_results(&self) -> &[(TaskId, TaskResult)] {
+    &self.frame_results
+  }
+
+  /// time spent updating, the sum of every frame's delta.
+  /// real time, it doesn't stop for the time service pausing.
+  pub fn clock(&self) -> Duration {
+    self.clock
+  }
+
+  /// pausing, slowing down and stepping the time handed to tasks
+  pub fn time(&self) -> &time::TimeService {
+    &self.time
+  }
+
+  /// how many frames have been updated, the next frame's TimeStep::frame
+  pub fn frame_index(&self) -> u64 {
+    self.frame_index
+  }
+
+  /// the registry every task links its channels through, without any permission checks
+  pub fn channel_registry(&self) -> channel::ChannelRegistry<M> {
+    self.hardware_registry.clone()
+  }
+
+  /// sets the capacity and backpressure policy of a channel or topic, before any task links to it
+  pub fn configure_channel(&self, id: &'static str, config: channel::ChannelConfig) {
+    self.hardware_registry.configure(id, config);
+  }
+
+  /// queue depth, dropped messages and throughput counters for every channel
+  pub fn channel_metrics(&self) -> Vec<channel::ChannelMetrics> {
+    self.hardware_registry.metrics()
+  }
+
+  /// records every message sent on the channel or topic, see recording::Recorder.
+  /// call it before adding the tasks that use the channel, channels linked earlier aren't recorded.
+  pub fn record_channel(&mut self, id: &'static str, recorder: &recording::Recorder)
+  where
+    M: codec::MessageCodec,
+  {
+    if self.recorder.is_none() {
+      for task in &self.tasks {
+        recorder.name_task(task.get_id(), task.get_label());
+      }
+      self.recorder = Some(recorder.clone());
+    }
+    self.hardware_registry.tap(id, recorder.tap());
+  }
+
+  /// timings for every task, see profiler::Profiler::save_chrome_trace for the timeline
+  pub fn profiler(&self) -> &profiler::Profiler {
+    &self.profiler
+  }
+
+  pub fn profiler_mut(&mut self) -> &mut profiler::Profiler {
+    &mut self.profiler
+  }
+
+  /// the task's update future runs on the async_std executor, and is polled once per frame
+  /// without blocking it. see async_task::AsyncTask
+  pub fn add_async_task<TaskT: async_task::AsyncTask<M> + 'static>(
+    &mut self,
+    task: TaskT,
+    perms: container::TaskPermission,
+  ) -> anyhow::Result<()> {
+    self.add_task(async_task::AsyncTaskRunner::new(task), perms)
+  }
+
+  /// loads a task from a library built with export_task!, and swaps in the new code whenever
+  /// the library is rebuilt. see hot_reload::DylibTask
+  pub fn add_dylib_task(
+    &mut self,
+    path: impl Into<std::path::PathBuf>,
+    perms: container::TaskPermission,
+  ) -> anyhow::Result<()> {
+    let task = hot_reload::DylibTask::<M>::load(path)?;
+    self.add_task(task, perms)
+  }
+
+  /// every action a task tried without the permission for it, oldest first
+  pub fn denied_actions(&self) -> impl Iterator<Item = &PermissionDenied> {
+    self.denied_log.iter()
+  }
+
+  pub fn get_label(&self, task: TaskId) -> Option<&'static str> {
+    self
+      .tasks
+      .iter()
+      .find(|container| container.get_id() == task)
+      .map(|container| container.get_label())
+  }
+
+  /// the task's label for log lines, or its id once it's been removed
+  fn describe_task(&self, task: TaskId) -> String {
+    match self.get_label(task) {
+      Some(label) => label.to_string(),
+      None => format!("{:?}", task),
+    }
+  }
+
+  /// every task the manager is holding on to, in the order they were added
+  pub fn list_tasks(&self) -> Vec<TaskInfo> {
+    self.tasks.iter().map(|task| task.get_info()).collect()
+  }
+
+  /// calls end() on every task with the label, and stops updating them until they're resumed
+  pub fn stop_task(&mut self, label: &str) -> anyhow::Result<()> {
+    self.for_each_labeled(label, profiler::TaskPhase::End, |task| task.stop_task())
+  }
+
+  /// calls start() on every stopped task with the label, and starts updating them again
+  pub fn resume_task(&mut self, label: &str) -> anyhow::Result<()> {
+    self.for_each_labeled(label, profiler::TaskPhase::Start, |task| task.resume_task())
+  }
+
+  /// ends and removes every task with the label
+  pub fn unload_task(&mut self, label: &str) -> anyhow::Result<()> {
+    let mut result =
+      self.for_each_labeled(label, profiler::TaskPhase::End, |task| task.stop_task());
+
+    self.tasks.retain(|task| task.get_label() != label);
+    // taking tasks away can't create a cycle, but it does move the indices around
+    match ordering::sort_tasks(&self.tasks) {
+      Ok(stages) => self.stages = stages,
+      Err(error) => result = Err(error),
+    }
+
+    result
+  }
+
+  fn for_each_labeled(
+    &mut self,
+    label: &str,
+    phase: profiler::TaskPhase,
+    mut action: impl FnMut(&mut container::TaskContainer<M>) -> anyhow::Result<()>,
+  ) -> anyhow::Result<()> {
+    let mut found = false;
+    let mut result = Ok(());
+
+    for task in self
+      .tasks
+      .iter_mut()
+      .filter(|task| task.get_label() == label)
+    {
+      found = true;
+      let (action_result, mut span) =
+        profiler::measure(self.profiler.epoch(), phase, || action(task));
+      span.task = task.get_label();
+      self.profiler.record(span);
+
+      if let Err(error) = action_result {
+        result = Err(error);
+      }
+    }
+
+    if !found {
+      return Err(anyhow::anyhow!("no task with the label \"{}\"", label));
+    }
+    result
+  }
+
+  /// every failure, restart, and disabled task, oldest first
+  pub fn restart_log(&self) -> impl Iterator<Item = &supervisor::RestartRecord> {
+    self.restart_log.iter()
+  }
+
   pub fn add_task<TaskT: Task<M> + 'static>(
     &mut self,
     task: TaskT,
     perms: container::TaskPermission,
   ) -> anyhow::Result<()> {
-    let task = container::TaskContainer::new(task, perms, self.hardware_registry.clone())?;
+    let task_id = TaskId(self.next_task_id);
+    self.next_task_id += 1;
+
+    let channel_registry =
+      self
+        .hardware_registry
+        .scoped(task_id, perms.clone(), self.denied_sender.clone());
+    let manager_handle =
+      ManagerHandle::new(task_id, self.control_sender.clone(), self.time.clone());
+    let (task, mut span) =
+      profiler::measure(self.profiler.epoch(), profiler::TaskPhase::Start, || {
+        container::TaskContainer::new(
+          task,
+          task_id,
+          perms,
+          channel_registry,
+          manager_handle,
+          self.watchdog.clone(),
+        )
+      });
+    let task = match task {
+      Ok(task) => task,
+      Err(error) => {
+        let error = error::TaskError::new(error).with_task(task_id, std::any::type_name::<TaskT>());
+        logger::error!("{:#}", error);
+        self.publish_error(error.clone());
+        return Err(error.into());
+      }
+    };
+    span.task = task.get_label();
+    self.profiler.record(span);
+    if let Some(recorder) = &self.recorder {
+      recorder.name_task(task_id, task.get_label());
+    }
     self.tasks.push(task);
+    self.link_pending_channels();
+    self.report_denied_actions();
+
+    // a task that closes a dependency cycle is rejected, leaving the old order intact.
+    match ordering::sort_tasks(&self.tasks) {
+      Ok(stages) => self.stages = stages,
+      Err(error) => {
+        if let Some(rejected) = self.tasks.pop() {
+          let _ = rejected.end_task();
+        }
+        return Err(error);
+      }
+    }
+
     Ok(())
   }
 
-  pub fn update_tasks(&self) -> UpdateReturn {
-    for task in &self.tasks {
-      let task_result = task.run();
-      match task_result {
-        TaskResult::ErrFatal(msg) => println!(
-          "task {} returned with fatal error: {}",
-          task.get_label(),
-          msg
-        ),
-        TaskResult::ErrReload => {
-          // attempt to reload, TODO: handle this error properly
-          if let Err(error) = task.reload_task(self.hardware_registry.clone()) {
-            println!("ERROR: {:?}", error);
-            return UpdateReturn::Shutdown;
-          }
+  pub fn update_tasks(&mut self) -> UpdateReturn {
+    let now = Instant::now();
+    let delta = match self.last_update {
+      Some(last_update) => now - last_update,
+      None => Duration::ZERO,
+    };
+    self.last_update = Some(now);
+
+    self.update_tasks_with_delta(delta)
+  }
+
+  /// same as update_tasks, but the frame time is given by the caller instead of measured.
+  pub fn update_tasks_with_delta(&mut self, delta: Duration) -> UpdateReturn {
+    let frame_time = self.time.advance(delta);
+    let frame_index = self.frame_index;
+    self.clock += delta;
+    self.frame_index += 1;
+    logger::set_frame(Some(frame_index));
+    self.frame_results.clear();
+    if let Some(recorder) = &self.recorder {
+      recorder.next_tick(delta);
+    }
+    self.handle_control_messages();
+    self.link_pending_channels();
+    self.report_denied_actions();
+    self.collect_hang_reports();
+    if let UpdateReturn::Shutdown = self.restart_due_tasks() {
+      return UpdateReturn::Shutdown;
+    }
+
+    let frame = self.plan_frame(frame_index, &frame_time);
+
+    for stage_index in 0..self.stages.len() {
+      let results = self.run_stage(&self.stages[stage_index], &frame);
+
+      // handled in stage order, so the results are the same with or without workers
+      let mut update_return = UpdateReturn::Ok;
+      for (index, task_result, span) in results {
+        if let Some(span) = span {
+          self.profiler.record(span);
+        }
+        let task_id = self.tasks[index].get_id();
+        self.frame_results.push((task_id, task_result.clone()));
+
+        if let UpdateReturn::Shutdown = self.handle_result(index, task_result) {
+          update_return = UpdateReturn::Shutdown;
+        }
+      }
+
+      if let UpdateReturn::Shutdown = update_return {
+        return UpdateReturn::Shutdown;
+      }
+    }
+
+    return UpdateReturn::Ok;
+  }
+
+  /// advances every task's clock, returning how many steps each task runs this frame.
+  /// variable rate tasks get the alpha of the first fixed rate task in update order.
+  fn plan_frame(
+    &mut self,
+    frame_index: u64,
+    frame_time: &time::FrameTime,
+  ) -> Vec<(u32, timestep::TimeStep)> {
+    let mut frame: Vec<(u32, timestep::TimeStep)> = self
+      .tasks
+      .iter_mut()
+      .map(|task| task.advance_clock(frame_time.delta))
+      .collect();
+
+    let alpha = self
+      .stages
+      .iter()
+      .flatten()
+      .find_map(|&index| self.tasks[index].fixed_alpha())
+      .unwrap_or(0.0);
+
+    for (task, (steps, timestep)) in self.tasks.iter().zip(frame.iter_mut()) {
+      if !task.is_runnable() {
+        *steps = 0;
+      }
+      if task.fixed_alpha().is_none() {
+        timestep.alpha = alpha;
+      }
+      timestep.unscaled_delta = frame_time.unscaled_delta;
+      timestep.frame = frame_index;
+      timestep.elapsed = frame_time.elapsed;
+      timestep.paused = frame_time.paused;
+    }
+
+    frame
+  }
+
+  /// runs every task in the stage, on the workers if there are any.
+  /// the stage is finished before the next one starts, so dependencies hold between threads.
+  fn run_stage(
+    &self,
+    stage: &[usize],
+    frame: &[(u32, timestep::TimeStep)],
+  ) -> Vec<(usize, TaskResult, Option<profiler::Span>)> {
+    let mut results = Vec::with_capacity(stage.len());
+
+    let Some(workers) = &self.workers else {
+      for &index in stage {
+        let (steps, timestep) = frame[index];
+        if steps > 0 {
+          let (task_result, span) = self.run_task(index, steps, timestep);
+          results.push((index, task_result, Some(span)));
+        }
+      }
+      return results;
+    };
+
+    let mut dispatched = 0;
+    for &index in stage {
+      let task = &self.tasks[index];
+      let (steps, timestep) = frame[index];
+      if steps == 0 || task.get_tag().contains(&TaskTag::MainThread) {
+        continue;
+      }
+      match workers.dispatch(index, task.clone(), steps, timestep, self.profiler.epoch()) {
+        Ok(()) => dispatched += 1,
+        Err(error) => {
+          logger::error!("{:?}", error);
+          let error = error::TaskError::new(error.context("failed to dispatch task"));
+          results.push((index, TaskResult::ErrFatal(error), None));
+        }
+      }
+    }
+
+    // the main thread tasks run while the workers are busy
+    for &index in stage {
+      let task = &self.tasks[index];
+      let (steps, timestep) = frame[index];
+      if steps > 0 && task.get_tag().contains(&TaskTag::MainThread) {
+        let (task_result, span) = self.run_task(index, steps, timestep);
+        results.push((index, task_result, Some(span)));
+      }
+    }
+
+    for _ in 0..dispatched {
+      match workers.collect() {
+        Some((index, task_result, span)) => results.push((index, task_result, Some(span))),
+        None => break,
+      }
+    }
+
+    results.sort_by_key(|(index, _, _)| stage.iter().position(|slot| slot == index));
+    results
+  }
+
+  fn run_task(
+    &self,
+    index: usize,
+    steps: u32,
+    timestep: timestep::TimeStep,
+  ) -> (TaskResult, profiler::Span) {
+    let task = &self.tasks[index];
+    let (task_result, mut span) =
+      profiler::measure(self.profiler.epoch(), profiler::TaskPhase::Update, || {
+        task.run(steps, timestep)
+      });
+    span.task = task.get_label();
+    (task_result, span)
+  }
+
+  fn handle_result(&mut self, index: usize, task_result: TaskResult) -> UpdateReturn {
+    match task_result {
+      TaskResult::ErrFatal(error) => {
+        return self.task_failed(index, error.context("returned with fatal error"));
+      }
+      TaskResult::ErrReload => {
+        return self.task_failed(index, error::TaskError::msg("requested a reload"));
+      }
+      TaskResult::ErrPanicked(message) => {
+        let error = error::TaskError::msg(format!("panicked: {}", message));
+        return self.task_failed(index, error);
+      }
+      TaskResult::Ok => {
+        let now = self.clock;
+        self.tasks[index].get_supervisor_mut().succeeded(now);
+      }
+      TaskResult::RequestShutdown => {
+        let task = &self.tasks[index];
+        if task.get_permission().can_request_shutdown() {
+          return UpdateReturn::Shutdown;
         }
-        TaskResult::Ok => {}
-        TaskResult::RequestShutdown => {
-          if *task.get_permission() == TaskPermission::Root {
+
+        let _ = self.denied_sender.send(PermissionDenied {
+          task: task.get_id(),
+          permission: task.get_permission().clone(),
+          action: DeniedAction::RequestShutdown,
+        });
+      }
+    }
+
+    return UpdateReturn::Ok;
+  }
+
+  /// hands the failure to the task's restart policy, and to everyone subscribed to errors
+  fn task_failed(&mut self, index: usize, error: error::TaskError) -> UpdateReturn {
+    let task = &self.tasks[index];
+    let error = error.with_task(task.get_id(), task.get_label());
+    let reason = format!("{:#}", error);
+    self.publish_error(error);
+
+    let now = self.clock;
+    let supervisor = self.tasks[index].get_supervisor_mut();
+    let verdict = supervisor.fail(now);
+    let attempt = supervisor.retries();
+
+    let (action, update_return) = match verdict {
+      supervisor::Verdict::Restart { backoff } => (
+        supervisor::RestartAction::Scheduled { backoff },
+        UpdateReturn::Ok,
+      ),
+      supervisor::Verdict::Disable => (supervisor::RestartAction::Disabled, UpdateReturn::Ok),
+      supervisor::Verdict::Escalate => {
+        (supervisor::RestartAction::Escalated, UpdateReturn::Shutdown)
+      }
+    };
+
+    self.record_restart(index, attempt, reason, action);
+    update_return
+  }
+
+  /// restarts every task whose backoff has run out
+  fn restart_due_tasks(&mut self) -> UpdateReturn {
+    let now = self.clock;
+
+    for index in 0..self.tasks.len() {
+      if !self.tasks[index].get_supervisor().restart_due(now) {
+        continue;
+      }
+
+      let attempt = self.tasks[index].get_supervisor().retries();
+      let task = &self.tasks[index];
+      let (reload_result, mut span) =
+        profiler::measure(self.profiler.epoch(), profiler::TaskPhase::Reload, || {
+          task.reload_task()
+        });
+      span.task = task.get_label();
+      self.profiler.record(span);
+
+      match reload_result {
+        Ok(()) => {
+          self.tasks[index].get_supervisor_mut().restarted(now);
+          let reason = String::from("backoff finished");
+          self.record_restart(index, attempt, reason, supervisor::RestartAction::Restarted);
+        }
+        Err(error) => {
+          let error = error::TaskError::new(error.context("failed to restart"));
+          if let UpdateReturn::Shutdown = self.task_failed(index, error) {
             return UpdateReturn::Shutdown;
-          } else {
-            // TODO: handle this case
           }
         }
       }
@@ -106,4 +810,121 @@ impl<M: Clone + Send + 'static> UpdateManager<M> {
 
     return UpdateReturn::Ok;
   }
+
+  fn record_restart(
+    &mut self,
+    index: usize,
+    attempt: u32,
+    reason: String,
+    action: supervisor::RestartAction,
+  ) {
+    let record = supervisor::RestartRecord {
+      task: self.tasks[index].get_label(),
+      attempt,
+      reason,
+      action,
+      at: self.clock,
+    };
+    logger::warn!(
+      "task {} {}: {:?} (attempt {})",
+      record.task, record.reason, record.action, record.attempt
+    );
+
+    if self.restart_log.len() == RECORD_LOG_LENGTH {
+      self.restart_log.pop_front();
+    }
+    self.restart_log.push_back(record);
+  }
+
+  /// moves what the watchdog caught since the last frame into the log, it has already printed them
+  fn collect_hang_reports(&mut self) {
+    while let Some(report) = self.hang_receiver.try_recv() {
+      if self.hang_log.len() == RECORD_LOG_LENGTH {
+        self.hang_log.pop_front();
+      }
+      self.hang_log.push_back(report);
+    }
+  }
+
+  /// moves the denied actions reported by tasks and their registries into the log
+  fn report_denied_actions(&mut self) {
+    while let Some(denied) = self.denied_receiver.try_recv() {
+      logger::warn!(
+        "task {} was denied {:?}, it only has {:?} permission",
+        self.describe_task(denied.task),
+        denied.action,
+        denied.permission
+      );
+
+      if self.denied_log.len() == RECORD_LOG_LENGTH {
+        self.denied_log.pop_front();
+      }
+      self.denied_log.push_back(denied);
+    }
+  }
+
+  fn link_pending_channels(&mut self) {
+    for task in &mut self.tasks {
+      task.link_pending_channels();
+    }
+  }
+
+  /// carries out whatever tasks sent through their ManagerHandle since the last frame
+  fn handle_control_messages(&mut self) {
+    while let Some((sender, message)) = self.control_receiver.try_recv() {
+      let permission = self
+        .tasks
+        .iter()
+        .find(|task| task.get_id() == sender)
+        .map(|task| task.get_permission().clone());
+
+      // listing tasks is harmless, everything else needs to be able to manage tasks
+      let allowed = match (&message, &permission) {
+        (ManagerMessage::ListTasks(_), _) => true,
+        (_, Some(permission)) => permission.can_manage_tasks(),
+        (_, None) => false,
+      };
+
+      if !allowed {
+        let action = match &message {
+          ManagerMessage::ControlTime(_) => DeniedAction::ControlTime,
+          _ => DeniedAction::ManageTasks,
+        };
+        let _ = self.denied_sender.send(PermissionDenied {
+          task: sender,
+          permission: permission.unwrap_or(container::TaskPermission::Sandboxed(&[])),
+          action,
+        });
+        continue;
+      }
+
+      let result = match message {
+        ManagerMessage::SpawnTask(task, permission) => self.add_task(task, permission),
+        ManagerMessage::StopTask(label) => self.stop_task(&label),
+        ManagerMessage::ResumeTask(label) => self.resume_task(&label),
+        ManagerMessage::UnloadTask(label) => self.unload_task(&label),
+        ManagerMessage::ListTasks(reply) => {
+          let _ = reply.send(self.list_tasks());
+          Ok(())
+        }
+        ManagerMessage::ControlTime(control) => {
+          match control {
+            TimeControl::Pause => self.time.pause(),
+            TimeControl::Resume => self.time.resume(),
+            TimeControl::Step => self.time.step(),
+            TimeControl::SetTimeScale(time_scale) => self.time.set_time_scale(time_scale),
+          }
+          Ok(())
+        }
+      };
+
+      if let Err(error) = result {
+        logger::error!(
+          "task {} sent a manager message that failed: {:#}",
+          self.describe_task(sender),
+          error
+        );
+      }
+    }
+  }
 }
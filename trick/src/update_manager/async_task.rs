@@ -0,0 +1,262 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll, Waker};
+
+use crate::update_manager::{
+  PostInit, Task, TaskResult,
+  channel::{AnyChannel, ChannelRegistry, TaskChannel},
+  control::ManagerHandle,
+  snapshot::Snapshot,
+  timestep::TimeStep,
+};
+
+pub type TaskFuture = Pin<Box<dyn Future<Output = TaskResult> + Send + 'static>>;
+
+/// a task whose update returns a future instead of blocking the frame.
+/// the future runs on the async_std executor, and is checked on once per frame,
+/// so adapter requests, asset loads and network io can overlap with the other tasks.
+///
+/// the future is 'static, so anything it needs from the task has to be moved or cloned into it
+/// (channels, Arcs). only one update is in flight at a time, the next one is started
+/// as soon as the last one has finished.
+pub trait AsyncTask<M: Clone + Send + 'static>: Send {
+  fn start(&mut self, channel_registry: ChannelRegistry<M>) -> anyhow::Result<PostInit>;
+  fn update(&mut self) -> TaskFuture;
+  fn end(&mut self) -> anyhow::Result<()>;
+
+  fn timestep(&mut self, _timestep: &TimeStep) {}
+  fn channel_linked(&mut self, _id: &'static str, _channel: TaskChannel<M>) {}
+  fn typed_channel_linked(&mut self, _id: &'static str, _channel: AnyChannel) {}
+  fn manager_linked(&mut self, _manager: ManagerHandle<M>) {}
+  fn snapshot(&mut self) -> Option<&mut dyn Snapshot> {
+    None
+  }
+}
+
+/// runs an AsyncTask as a regular Task, see UpdateManager::add_async_task
+pub struct AsyncTaskRunner<M: Clone + Send + 'static, TaskT: AsyncTask<M>> {
+  task: TaskT,
+  in_flight: Option<async_std::task::JoinHandle<TaskResult>>,
+  _message: std::marker::PhantomData<fn() -> M>,
+}
+
+impl<M: Clone + Send + 'static, TaskT: AsyncTask<M>> AsyncTaskRunner<M, TaskT> {
+  pub fn new(task: TaskT) -> Self {
+    Self {
+      task,
+      in_flight: None,
+      _message: std::marker::PhantomData,
+    }
+  }
+
+  pub fn is_in_flight(&self) -> bool {
+    self.in_flight.is_some()
+  }
+
+  /// the executor wakes nobody, the manager polls again next frame anyway
+  fn poll_in_flight(&mut self) -> Option<TaskResult> {
+    let in_flight = self.in_flight.as_mut()?;
+    let mut context = Context::from_waker(Waker::noop());
+
+    match Pin::new(in_flight).poll(&mut context) {
+      Poll::Ready(task_result) => {
+        self.in_flight = None;
+        Some(task_result)
+      }
+      Poll::Pending => None,
+    }
+  }
+
+  fn cancel_in_flight(&mut self) {
+    if let Some(in_flight) = self.in_flight.take() {
+      // waits for the future to reach its next await point
+      async_std::task::block_on(in_flight.cancel());
+    }
+  }
+}
+
+impl<M: Clone + Send + 'static, TaskT: AsyncTask<M>> Task<M> for AsyncTaskRunner<M, TaskT> {
+  fn start(&mut self, channel_registry: ChannelRegistry<M>) -> anyhow::Result<PostInit> {
+    self.task.start(channel_registry)
+  }
+
+  fn update(&mut self) -> TaskResult {
+    let task_result = match self.in_flight {
+      Some(_) => match self.poll_in_flight() {
+        Some(task_result) => task_result,
+        // still running, nothing to report this frame
+        None => return TaskResult::Ok,
+      },
+      None => TaskResult::Ok,
+    };
+
+    // a failed task gets restarted before it updates again
+    if let TaskResult::Ok = task_result {
+      self.in_flight = Some(async_std::task::spawn(self.task.update()));
+    }
+    task_result
+  }
+
+  fn end(&mut self) -> anyhow::Result<()> {
+    self.cancel_in_flight();
+    self.task.end()
+  }
+
+  fn timestep(&mut self, timestep: &TimeStep) {
+    self.task.timestep(timestep)
+  }
+
+  fn channel_linked(&mut self, id: &'static str, channel: TaskChannel<M>) {
+    self.task.channel_linked(id, channel)
+  }
+
+  fn typed_channel_linked(&mut self, id: &'static str, channel: AnyChannel) {
+    self.task.typed_channel_linked(id, channel)
+  }
+
+  fn manager_linked(&mut self, manager: ManagerHandle<M>) {
+    self.task.manager_linked(manager)
+  }
+
+  fn snapshot(&mut self) -> Option<&mut dyn Snapshot> {
+    self.task.snapshot()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::collections::VecDeque;
+  use std::sync::Arc;
+  use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+  use std::time::{Duration, Instant};
+
+  use super::*;
+
+  /// every update's future waits for the gate, then returns the next result
+  struct Gated {
+    gate: Arc<AtomicBool>,
+    results: VecDeque<TaskResult>,
+    started: Arc<AtomicU32>,
+    finished: Arc<AtomicU32>,
+  }
+
+  impl AsyncTask<u32> for Gated {
+    fn start(&mut self, _: ChannelRegistry<u32>) -> anyhow::Result<PostInit> {
+      Ok(PostInit {
+        name: "gated",
+        tags: &[],
+        requests: &[],
+      })
+    }
+
+    fn update(&mut self) -> TaskFuture {
+      self.started.fetch_add(1, Ordering::SeqCst);
+      let gate = self.gate.clone();
+      let finished = self.finished.clone();
+      let task_result = self.results.pop_front().unwrap_or(TaskResult::Ok);
+      Box::pin(async move {
+        while !gate.load(Ordering::SeqCst) {
+          async_std::task::sleep(Duration::from_millis(1)).await;
+        }
+        finished.fetch_add(1, Ordering::SeqCst);
+        task_result
+      })
+    }
+
+    fn end(&mut self) -> anyhow::Result<()> {
+      Ok(())
+    }
+  }
+
+  struct Counts {
+    gate: Arc<AtomicBool>,
+    started: Arc<AtomicU32>,
+    finished: Arc<AtomicU32>,
+  }
+
+  fn gated(results: impl IntoIterator<Item = TaskResult>) -> (AsyncTaskRunner<u32, Gated>, Counts) {
+    let counts = Counts {
+      gate: Arc::new(AtomicBool::new(false)),
+      started: Arc::new(AtomicU32::new(0)),
+      finished: Arc::new(AtomicU32::new(0)),
+    };
+    let mut runner = AsyncTaskRunner::new(Gated {
+      gate: counts.gate.clone(),
+      results: results.into_iter().collect(),
+      started: counts.started.clone(),
+      finished: counts.finished.clone(),
+    });
+    runner.start(ChannelRegistry::new()).unwrap();
+    (runner, counts)
+  }
+
+  /// updates like the manager would every frame, until the in flight future is done
+  fn update_until_finished(runner: &mut AsyncTaskRunner<u32, Gated>) -> TaskResult {
+    let deadline = Instant::now() + Duration::from_secs(2);
+    let started = runner.task.started.load(Ordering::SeqCst);
+    loop {
+      let task_result = runner.update();
+      if task_result != TaskResult::Ok || runner.task.started.load(Ordering::SeqCst) > started {
+        return task_result;
+      }
+      assert!(Instant::now() < deadline, "the future never finished");
+      std::thread::sleep(Duration::from_millis(1));
+    }
+  }
+
+  #[test]
+  fn a_pending_future_doesnt_hold_up_updates() {
+    let (mut runner, counts) = gated([]);
+    assert_eq!(runner.update(), TaskResult::Ok);
+    assert!(runner.is_in_flight());
+
+    // every frame until it's done just checks on it, without starting another
+    for _ in 0..5 {
+      assert_eq!(runner.update(), TaskResult::Ok);
+      std::thread::sleep(Duration::from_millis(1));
+    }
+    assert_eq!(counts.started.load(Ordering::SeqCst), 1);
+    assert_eq!(counts.finished.load(Ordering::SeqCst), 0);
+
+    // once it's done, the next update starts the next one straight away
+    counts.gate.store(true, Ordering::SeqCst);
+    assert_eq!(update_until_finished(&mut runner), TaskResult::Ok);
+    assert_eq!(counts.finished.load(Ordering::SeqCst), 1);
+    assert_eq!(counts.started.load(Ordering::SeqCst), 2);
+    assert!(runner.is_in_flight());
+    runner.end().unwrap();
+  }
+
+  #[test]
+  fn errors_are_returned_by_the_update_that_finds_them() {
+    let (mut runner, counts) = gated([
+      TaskResult::Ok,
+      TaskResult::ErrFatal("lost the server".into()),
+    ]);
+    counts.gate.store(true, Ordering::SeqCst);
+    runner.update();
+    assert_eq!(update_until_finished(&mut runner), TaskResult::Ok);
+
+    assert_eq!(
+      update_until_finished(&mut runner),
+      TaskResult::ErrFatal("lost the server".into())
+    );
+    // a failed task isn't updated again until it's been restarted
+    assert!(!runner.is_in_flight());
+    assert_eq!(counts.started.load(Ordering::SeqCst), 2);
+  }
+
+  #[test]
+  fn ending_cancels_the_future_in_flight() {
+    let (mut runner, counts) = gated([]);
+    runner.update();
+    assert!(runner.is_in_flight());
+
+    runner.end().unwrap();
+    assert!(!runner.is_in_flight());
+    // it never gets past the gate, even once it's opened
+    counts.gate.store(true, Ordering::SeqCst);
+    std::thread::sleep(Duration::from_millis(20));
+    assert_eq!(counts.finished.load(Ordering::SeqCst), 0);
+  }
+}
@@ -0,0 +1,803 @@
+use std::io::{BufReader, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+#[cfg(unix)]
+use std::os::unix::fs::FileTypeExt;
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+#[cfg(unix)]
+use std::path::PathBuf;
+
+use crate::update_manager::{
+  PostInit, Task, TaskRequest, TaskResult,
+  channel::{ChannelRegistry, TaskChannel, TaskReceiver, TaskSender},
+  codec::{self, ByteReader, MessageCodec},
+};
+
+const HELLO_MAGIC: &[u8; 8] = b"TRICKBRG";
+/// bumped whenever the framing or the hello changes
+pub const BRIDGE_VERSION: u32 = 1;
+
+/// how long a connecting bridge waits before trying again
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(1);
+/// how long a new connection has to say hello
+const HELLO_TIMEOUT: Duration = Duration::from_millis(500);
+/// how often the forwarding threads check whether the bridge was closed
+const CLOSE_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// where a bridge listens or connects. only local sockets, nothing leaves the machine.
+#[derive(Clone, Debug, PartialEq)]
+pub enum BridgeAddress {
+  #[cfg(unix)]
+  Unix(PathBuf),
+  /// has to be a loopback address, eg: 127.0.0.1:7878
+  Tcp(SocketAddr),
+}
+
+impl BridgeAddress {
+  fn check_local(&self) -> anyhow::Result<()> {
+    match self {
+      BridgeAddress::Tcp(address) if !address.ip().is_loopback() => {
+        anyhow::bail!("bridge address {} isn't a loopback address", address)
+      }
+      _ => Ok(()),
+    }
+  }
+}
+
+/// a socket carrying framed messages, see codec::write_frame
+pub enum BridgeStream {
+  #[cfg(unix)]
+  Unix(UnixStream),
+  Tcp(TcpStream),
+}
+
+impl BridgeStream {
+  pub fn connect(address: &BridgeAddress) -> anyhow::Result<Self> {
+    address.check_local()?;
+    Ok(match address {
+      #[cfg(unix)]
+      BridgeAddress::Unix(path) => BridgeStream::Unix(UnixStream::connect(path)?),
+      BridgeAddress::Tcp(address) => {
+        let stream = TcpStream::connect(address)?;
+        // messages are small and latency matters more than throughput
+        stream.set_nodelay(true)?;
+        BridgeStream::Tcp(stream)
+      }
+    })
+  }
+
+  pub fn try_clone(&self) -> anyhow::Result<Self> {
+    Ok(match self {
+      #[cfg(unix)]
+      BridgeStream::Unix(stream) => BridgeStream::Unix(stream.try_clone()?),
+      BridgeStream::Tcp(stream) => BridgeStream::Tcp(stream.try_clone()?),
+    })
+  }
+
+  /// None blocks forever
+  pub fn set_read_timeout(&self, timeout: Option<Duration>) -> anyhow::Result<()> {
+    match self {
+      #[cfg(unix)]
+      BridgeStream::Unix(stream) => stream.set_read_timeout(timeout)?,
+      BridgeStream::Tcp(stream) => stream.set_read_timeout(timeout)?,
+    }
+    Ok(())
+  }
+
+  /// unblocks anything reading from or writing to a clone of the stream
+  pub fn close(&self) {
+    let _ = match self {
+      #[cfg(unix)]
+      BridgeStream::Unix(stream) => stream.shutdown(Shutdown::Both),
+      BridgeStream::Tcp(stream) => stream.shutdown(Shutdown::Both),
+    };
+  }
+
+  /// both ends send one before anything else, so a bridge can't be linked to the wrong channel
+  pub fn send_hello(&mut self, channel: &str) -> anyhow::Result<()> {
+    let mut hello = Vec::new();
+    hello.extend_from_slice(HELLO_MAGIC);
+    codec::put_u32(&mut hello, BRIDGE_VERSION);
+    codec::put_str(&mut hello, channel);
+    codec::write_frame(self, &hello)?;
+    self.flush()?;
+    Ok(())
+  }
+
+  pub fn expect_hello(&mut self, channel: &str) -> anyhow::Result<()> {
+    let Some(hello) = codec::read_frame(self)? else {
+      anyhow::bail!("bridge closed before saying hello");
+    };
+    let Some(hello) = hello.strip_prefix(HELLO_MAGIC) else {
+      anyhow::bail!("not a bridge");
+    };
+    let mut hello = ByteReader::new(hello);
+    let version = hello.u32()?;
+    if version != BRIDGE_VERSION {
+      anyhow::bail!("bridge is version {}, expected {}", version, BRIDGE_VERSION);
+    }
+    let other_channel = hello.str()?;
+    if other_channel != channel {
+      anyhow::bail!(
+        "bridge is for channel \"{}\", not \"{}\"",
+        other_channel,
+        channel
+      );
+    }
+    Ok(())
+  }
+
+  pub fn send_message<M: MessageCodec>(&mut self, message: &M) -> anyhow::Result<()> {
+    let mut frame = Vec::new();
+    message.encode(&mut frame)?;
+    codec::write_frame(self, &frame)?;
+    self.flush()?;
+    Ok(())
+  }
+
+  /// blocks until a message arrives, None once the other side has closed the stream
+  pub fn recv_message<M: MessageCodec>(&mut self) -> anyhow::Result<Option<M>> {
+    match codec::read_frame(self)? {
+      Some(frame) => Ok(Some(M::decode(&frame)?)),
+      None => Ok(None),
+    }
+  }
+}
+
+impl Read for BridgeStream {
+  fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+    match self {
+      #[cfg(unix)]
+      BridgeStream::Unix(stream) => stream.read(buf),
+      BridgeStream::Tcp(stream) => stream.read(buf),
+    }
+  }
+}
+
+impl Write for BridgeStream {
+  fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+    match self {
+      #[cfg(unix)]
+      BridgeStream::Unix(stream) => stream.write(buf),
+      BridgeStream::Tcp(stream) => stream.write(buf),
+    }
+  }
+
+  fn flush(&mut self) -> std::io::Result<()> {
+    match self {
+      #[cfg(unix)]
+      BridgeStream::Unix(stream) => stream.flush(),
+      BridgeStream::Tcp(stream) => stream.flush(),
+    }
+  }
+}
+
+enum BridgeListener {
+  #[cfg(unix)]
+  Unix(UnixListener, PathBuf),
+  Tcp(TcpListener),
+}
+
+impl BridgeListener {
+  fn bind(address: &BridgeAddress) -> anyhow::Result<Self> {
+    address.check_local()?;
+    let listener = match address {
+      #[cfg(unix)]
+      BridgeAddress::Unix(path) => {
+        // left behind by a bridge that didn't shut down cleanly.
+        // anything that isn't a socket is someone else's file, so it's left alone.
+        match std::fs::symlink_metadata(path) {
+          Ok(metadata) if metadata.file_type().is_socket() => std::fs::remove_file(path)?,
+          Ok(_) => anyhow::bail!(
+            "can't bind bridge to {}, there's a file in the way",
+            path.display()
+          ),
+          Err(error) if error.kind() == std::io::ErrorKind::NotFound => {}
+          Err(error) => return Err(error.into()),
+        }
+        let listener = UnixListener::bind(path)?;
+        listener.set_nonblocking(true)?;
+        BridgeListener::Unix(listener, path.clone())
+      }
+      BridgeAddress::Tcp(address) => {
+        let listener = TcpListener::bind(address)?;
+        listener.set_nonblocking(true)?;
+        BridgeListener::Tcp(listener)
+      }
+    };
+    Ok(listener)
+  }
+
+  /// None if nobody is waiting to connect
+  fn accept(&self) -> anyhow::Result<Option<BridgeStream>> {
+    let accepted = match self {
+      #[cfg(unix)]
+      BridgeListener::Unix(listener, _) => listener.accept().map(|(stream, _)| {
+        stream
+          .set_nonblocking(false)
+          .map(|_| BridgeStream::Unix(stream))
+      }),
+      BridgeListener::Tcp(listener) => listener.accept().map(|(stream, _)| {
+        stream.set_nonblocking(false)?;
+        stream.set_nodelay(true)?;
+        Ok(BridgeStream::Tcp(stream))
+      }),
+    };
+    match accepted {
+      Ok(stream) => Ok(Some(stream?)),
+      Err(error) if error.kind() == std::io::ErrorKind::WouldBlock => Ok(None),
+      Err(error) => Err(error.into()),
+    }
+  }
+
+  /// the address a tcp bridge ended up on, useful when bound to port 0
+  fn local_address(&self) -> Option<BridgeAddress> {
+    match self {
+      #[cfg(unix)]
+      BridgeListener::Unix(_, path) => Some(BridgeAddress::Unix(path.clone())),
+      BridgeListener::Tcp(listener) => listener.local_addr().ok().map(BridgeAddress::Tcp),
+    }
+  }
+}
+
+impl Drop for BridgeListener {
+  fn drop(&mut self) {
+    #[cfg(unix)]
+    if let BridgeListener::Unix(_, path) = self {
+      let _ = std::fs::remove_file(path);
+    }
+  }
+}
+
+/// the thread forwarding one connection
+struct Connection {
+  stream: BridgeStream,
+  closed: Arc<AtomicBool>,
+  thread: JoinHandle<()>,
+}
+
+impl Connection {
+  /// the hello happens on the connection's thread, so the frame doesn't wait on it.
+  /// (two bridges updated by the same thread would never get to answer each other)
+  fn open<M: MessageCodec + Send + 'static>(
+    stream: BridgeStream,
+    channel_id: &'static str,
+    channel: &TaskChannel<M>,
+  ) -> anyhow::Result<Self> {
+    let closed = Arc::new(AtomicBool::new(false));
+    let (local_sender, local_receiver) = channel.clone().split();
+    let forwarding = stream.try_clone()?;
+    let thread_closed = closed.clone();
+    let thread = std::thread::Builder::new()
+      .name(format!("bridge {}", channel_id))
+      .spawn(move || {
+        forward(
+          forwarding,
+          local_sender,
+          local_receiver,
+          &thread_closed,
+          channel_id,
+        );
+        thread_closed.store(true, Ordering::Relaxed);
+      })?;
+    Ok(Self {
+      stream,
+      closed,
+      thread,
+    })
+  }
+
+  fn is_closed(&self) -> bool {
+    self.closed.load(Ordering::Relaxed)
+  }
+
+  fn close(self) {
+    self.closed.store(true, Ordering::Relaxed);
+    self.stream.close();
+    let _ = self.thread.join();
+  }
+}
+
+/// says hello, then forwards the socket to the local channel on this thread,
+/// and the local channel to the socket on a writer thread
+fn forward<M: MessageCodec + Send + 'static>(
+  mut stream: BridgeStream,
+  local_sender: TaskSender<M>,
+  local_receiver: TaskReceiver<M>,
+  closed: &Arc<AtomicBool>,
+  channel_id: &'static str,
+) {
+  if let Err(error) = handshake(&mut stream, channel_id) {
+    if !closed.load(Ordering::Relaxed) {
+      logger::error!("bridge \"{}\" failed to connect, {}", channel_id, error);
+    }
+    return;
+  }
+
+  let writer = stream.try_clone().and_then(|writer_stream| {
+    spawn_writer(writer_stream, local_receiver, closed.clone(), channel_id)
+  });
+  let writer = match writer {
+    Ok(writer) => writer,
+    Err(error) => {
+      logger::error!(
+        "bridge \"{}\" failed to start writing, {}",
+        channel_id,
+        error
+      );
+      return;
+    }
+  };
+
+  read_messages(stream, local_sender, closed, channel_id);
+  closed.store(true, Ordering::Relaxed);
+  let _ = writer.join();
+}
+
+fn handshake(stream: &mut BridgeStream, channel_id: &'static str) -> anyhow::Result<()> {
+  // a peer that never says hello is dropped
+  stream.set_read_timeout(Some(HELLO_TIMEOUT))?;
+  stream.send_hello(channel_id)?;
+  stream.expect_hello(channel_id)?;
+  stream.set_read_timeout(None)
+}
+
+/// socket -> local channel, until either side is closed
+fn read_messages<M: MessageCodec + Send + 'static>(
+  stream: BridgeStream,
+  local: TaskSender<M>,
+  closed: &AtomicBool,
+  channel_id: &'static str,
+) {
+  let mut stream = BufReader::new(stream);
+  while !closed.load(Ordering::Relaxed) {
+    let message = match codec::read_frame(&mut stream) {
+      Ok(Some(frame)) => M::decode(&frame),
+      Ok(None) => break,
+      Err(error) => {
+        if !closed.load(Ordering::Relaxed) {
+          logger::error!("bridge \"{}\" failed to read, {}", channel_id, error);
+        }
+        break;
+      }
+    };
+    match message {
+      Ok(message) => {
+        if local.send(message).is_err() {
+          break;
+        }
+      }
+      Err(error) => {
+        logger::error!(
+          "bridge \"{}\" failed to decode a message, {}",
+          channel_id,
+          error
+        );
+        break;
+      }
+    }
+  }
+}
+
+/// local channel -> socket
+fn spawn_writer<M: MessageCodec + Send + 'static>(
+  mut stream: BridgeStream,
+  local: TaskReceiver<M>,
+  closed: Arc<AtomicBool>,
+  channel_id: &'static str,
+) -> anyhow::Result<JoinHandle<()>> {
+  let thread = std::thread::Builder::new()
+    .name(format!("bridge writer {}", channel_id))
+    .spawn(move || {
+      while !closed.load(Ordering::Relaxed) {
+        let Some(message) = local.recv_timeout(CLOSE_POLL_INTERVAL) else {
+          if local.is_disconnected() {
+            break;
+          }
+          continue;
+        };
+        if let Err(error) = stream.send_message(&message) {
+          if !closed.load(Ordering::Relaxed) {
+            logger::error!("bridge \"{}\" failed to write, {}", channel_id, error);
+          }
+          break;
+        }
+      }
+      closed.store(true, Ordering::Relaxed);
+    })?;
+  Ok(thread)
+}
+
+/// PostInit wants the requests 'static, so they're leaked once per channel ID,
+/// and shared by every bridge for it after that
+fn link_requests(channel_id: &'static str) -> &'static [TaskRequest] {
+  static REQUESTS: Mutex<Vec<&'static [TaskRequest]>> = Mutex::new(Vec::new());
+
+  let mut requests = REQUESTS
+    .lock()
+    .unwrap_or_else(|poisoned| poisoned.into_inner());
+  if let Some(linked) = requests
+    .iter()
+    .find(|linked| linked[0] == TaskRequest::LinkChannel(channel_id))
+  {
+    return linked;
+  }
+  let linked: &'static [TaskRequest] = Box::leak(Box::new([TaskRequest::LinkChannel(channel_id)]));
+  requests.push(linked);
+  linked
+}
+
+enum BridgeRole {
+  Listen(BridgeListener),
+  Connect {
+    address: BridgeAddress,
+    /// no attempts before this
+    next_attempt: Instant,
+  },
+}
+
+/// links to a channel ID like any other task, and forwards it over a local socket,
+/// so an editor, a test driver or a second game instance can stand in for a local task.
+/// one connection at a time, a listening bridge accepts the next one once it's gone,
+/// and a connecting bridge keeps trying to reconnect.
+///
+/// the other side can be another BridgeTask, or anything speaking the same frames through
+/// BridgeStream: a hello, then one codec::write_frame per message encoded with MessageCodec.
+///
+/// ```ignore
+/// // in the game
+/// let bridge = BridgeTask::listen("input", "input bridge", tcp_address)?;
+/// manager.add_task(bridge, TaskPermission::User)?;
+/// // in the test driver
+/// let mut stream = BridgeStream::connect(&tcp_address)?;
+/// stream.send_hello("input")?;
+/// stream.expect_hello("input")?;
+/// stream.send_message(&HardwareMessage::Window(WindowEvent::FocusLost))?;
+/// ```
+pub struct BridgeTask<M: Clone + Send + 'static> {
+  channel_id: &'static str,
+  /// given by the caller, so bridges can be told apart and ordered against
+  label: &'static str,
+  requests: &'static [TaskRequest],
+  role: BridgeRole,
+  channel: Option<TaskChannel<M>>,
+  connection: Option<Connection>,
+}
+
+impl<M: MessageCodec + Clone + Send + 'static> BridgeTask<M> {
+  /// binds right away, so a bad or taken address is an error here instead of in start()
+  pub fn listen(
+    channel_id: &'static str,
+    label: &'static str,
+    address: BridgeAddress,
+  ) -> anyhow::Result<Self> {
+    Ok(Self::new(
+      channel_id,
+      label,
+      BridgeRole::Listen(BridgeListener::bind(&address)?),
+    ))
+  }
+
+  pub fn connect(
+    channel_id: &'static str,
+    label: &'static str,
+    address: BridgeAddress,
+  ) -> anyhow::Result<Self> {
+    address.check_local()?;
+    Ok(Self::new(
+      channel_id,
+      label,
+      BridgeRole::Connect {
+        address,
+        next_attempt: Instant::now(),
+      },
+    ))
+  }
+
+  fn new(channel_id: &'static str, label: &'static str, role: BridgeRole) -> Self {
+    Self {
+      channel_id,
+      label,
+      requests: link_requests(channel_id),
+      role,
+      channel: None,
+      connection: None,
+    }
+  }
+
+  pub fn label(&self) -> &'static str {
+    self.label
+  }
+
+  /// where a listening bridge can be reached, None for a connecting bridge
+  pub fn local_address(&self) -> Option<BridgeAddress> {
+    match &self.role {
+      BridgeRole::Listen(listener) => listener.local_address(),
+      BridgeRole::Connect { .. } => None,
+    }
+  }
+
+  pub fn is_connected(&self) -> bool {
+    matches!(&self.connection, Some(connection) if !connection.is_closed())
+  }
+
+  fn next_stream(&mut self) -> anyhow::Result<Option<BridgeStream>> {
+    match &mut self.role {
+      BridgeRole::Listen(listener) => listener.accept(),
+      BridgeRole::Connect {
+        address,
+        next_attempt,
+      } => {
+        if Instant::now() < *next_attempt {
+          return Ok(None);
+        }
+        *next_attempt = Instant::now() + RECONNECT_INTERVAL;
+        // nobody listening yet isn't worth reporting, it's retried
+        Ok(BridgeStream::connect(address).ok())
+      }
+    }
+  }
+}
+
+impl<M: MessageCodec + Clone + Send + 'static> Task<M> for BridgeTask<M> {
+  fn start(&mut self, _channel_registry: ChannelRegistry<M>) -> anyhow::Result<PostInit> {
+    Ok(PostInit {
+      name: self.label,
+      tags: &[],
+      requests: self.requests,
+    })
+  }
+
+  fn channel_linked(&mut self, id: &'static str, channel: TaskChannel<M>) {
+    if id == self.channel_id {
+      self.channel = Some(channel);
+    }
+  }
+
+  fn update(&mut self) -> TaskResult {
+    if let Some(connection) = self.connection.take_if(|connection| connection.is_closed()) {
+      connection.close();
+    }
+
+    // wait for the local side before letting anyone in
+    let Some(channel) = self.channel.clone() else {
+      return TaskResult::Ok;
+    };
+    if self.connection.is_some() {
+      return TaskResult::Ok;
+    }
+
+    match self.next_stream() {
+      Ok(Some(stream)) => match Connection::open(stream, self.channel_id, &channel) {
+        Ok(connection) => self.connection = Some(connection),
+        Err(error) => logger::error!(
+          "bridge \"{}\" failed to start forwarding, {}",
+          self.channel_id,
+          error
+        ),
+      },
+      Ok(None) => {}
+      Err(error) => logger::error!("bridge \"{}\" failed to accept, {}", self.channel_id, error),
+    }
+    TaskResult::Ok
+  }
+
+  fn end(&mut self) -> anyhow::Result<()> {
+    if let Some(connection) = self.connection.take() {
+      connection.close();
+    }
+    self.channel = None;
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::time::Duration;
+
+  use super::*;
+  use crate::update_manager::{container::TaskPermission, harness::Harness};
+
+  const TICK: Duration = Duration::from_millis(10);
+  /// how long the peer waits on the bridge before the test fails instead of hanging
+  const PEER_TIMEOUT: Duration = Duration::from_secs(2);
+
+  #[derive(Clone, Debug, PartialEq)]
+  struct Note(String);
+
+  impl MessageCodec for Note {
+    fn encode(&self, out: &mut Vec<u8>) -> anyhow::Result<()> {
+      codec::put_str(out, &self.0);
+      Ok(())
+    }
+
+    fn decode(bytes: &[u8]) -> anyhow::Result<Self> {
+      Ok(Note(ByteReader::new(bytes).str()?.to_string()))
+    }
+  }
+
+  fn note(text: &str) -> Note {
+    Note(text.to_string())
+  }
+
+  /// ticks until the condition holds, giving the forwarding threads time to run
+  fn run_until(harness: &mut Harness<Note>, mut done: impl FnMut(&mut Harness<Note>) -> bool) {
+    let deadline = Instant::now() + PEER_TIMEOUT;
+    while !done(harness) {
+      assert!(
+        Instant::now() < deadline,
+        "the bridge didn't get there in time"
+      );
+      harness.tick();
+      std::thread::sleep(TICK);
+    }
+  }
+
+  fn listening_harness(channel_id: &'static str) -> (Harness<Note>, BridgeAddress) {
+    let mut harness = Harness::<Note>::new(TICK).unwrap();
+    let loopback = BridgeAddress::Tcp("127.0.0.1:0".parse().unwrap());
+    let bridge = BridgeTask::listen(channel_id, "notes bridge", loopback).unwrap();
+    let address = bridge.local_address().unwrap();
+    harness.add_task(bridge, TaskPermission::User).unwrap();
+    harness.probe(channel_id);
+    (harness, address)
+  }
+
+  fn connect_peer(address: &BridgeAddress, channel_id: &str) -> BridgeStream {
+    let mut peer = BridgeStream::connect(address).unwrap();
+    peer.set_read_timeout(Some(PEER_TIMEOUT)).unwrap();
+    peer.send_hello(channel_id).unwrap();
+    peer
+  }
+
+  #[test]
+  fn messages_cross_a_tcp_bridge_both_ways() {
+    let (mut harness, address) = listening_harness("notes");
+    harness.run(2);
+
+    // the bridge answers the hello without any more frames, it's on the forwarding thread
+    let mut peer = connect_peer(&address, "notes");
+    harness.tick();
+    peer.expect_hello("notes").unwrap();
+
+    harness.inject("notes", note("from the game"));
+    harness.tick();
+    assert_eq!(
+      peer.recv_message::<Note>().unwrap(),
+      Some(note("from the game"))
+    );
+
+    peer.send_message(&note("from the peer")).unwrap();
+    let mut captured = Vec::new();
+    run_until(&mut harness, |harness| {
+      captured.extend(harness.captured("notes"));
+      !captured.is_empty()
+    });
+    assert_eq!(captured, [note("from the peer")]);
+    harness.finish();
+  }
+
+  #[test]
+  fn a_listening_bridge_takes_the_next_peer_after_a_disconnect() {
+    let (mut harness, address) = listening_harness("notes");
+    harness.run(2);
+
+    let mut first = connect_peer(&address, "notes");
+    harness.tick();
+    first.expect_hello("notes").unwrap();
+    drop(first);
+
+    // the next peer only gets a hello once the bridge has let go of the first
+    let mut second = connect_peer(&address, "notes");
+    for _ in 0..20 {
+      harness.tick();
+      std::thread::sleep(TICK);
+    }
+    second.expect_hello("notes").unwrap();
+
+    second.send_message(&note("second peer")).unwrap();
+    let mut captured = Vec::new();
+    run_until(&mut harness, |harness| {
+      captured.extend(harness.captured("notes"));
+      !captured.is_empty()
+    });
+    assert_eq!(captured, [note("second peer")]);
+    harness.finish();
+  }
+
+  #[test]
+  fn a_hello_for_another_channel_is_refused() {
+    let (mut harness, address) = listening_harness("notes");
+    harness.run(2);
+
+    let mut peer = connect_peer(&address, "other");
+    harness.tick();
+    // the bridge says its own hello, then hangs up on the wrong one
+    assert!(peer.expect_hello("other").is_err());
+    assert!(matches!(peer.recv_message::<Note>(), Ok(None) | Err(_)));
+    harness.finish();
+  }
+
+  #[test]
+  fn non_loopback_addresses_are_refused() {
+    let outside = BridgeAddress::Tcp("8.8.8.8:7878".parse().unwrap());
+    assert!(BridgeTask::<Note>::connect("notes", "notes bridge", outside).is_err());
+  }
+
+  #[cfg(unix)]
+  #[test]
+  fn two_managers_link_over_a_unix_socket() {
+    let directory = std::env::temp_dir().join(format!("trick-bridge-{}", std::process::id()));
+    std::fs::create_dir_all(&directory).unwrap();
+    let path = directory.join("notes.sock");
+
+    let mut listening = Harness::<Note>::new(TICK).unwrap();
+    let bridge = BridgeTask::listen(
+      "notes",
+      "listening bridge",
+      BridgeAddress::Unix(path.clone()),
+    );
+    listening
+      .add_task(bridge.unwrap(), TaskPermission::User)
+      .unwrap();
+    let mut connecting = Harness::<Note>::new(TICK).unwrap();
+    let bridge = BridgeTask::connect(
+      "notes",
+      "connecting bridge",
+      BridgeAddress::Unix(path.clone()),
+    );
+    connecting
+      .add_task(bridge.unwrap(), TaskPermission::User)
+      .unwrap();
+    assert_eq!(
+      connecting
+        .manager()
+        .get_label(connecting.manager().list_tasks()[0].id),
+      Some("connecting bridge")
+    );
+
+    listening.inject("notes", note("to connecting"));
+    connecting.inject("notes", note("to listening"));
+    let (mut to_listening, mut to_connecting) = (Vec::new(), Vec::new());
+    let deadline = Instant::now() + PEER_TIMEOUT;
+    while to_listening.is_empty() || to_connecting.is_empty() {
+      assert!(Instant::now() < deadline, "the bridges didn't link in time");
+      listening.tick();
+      connecting.tick();
+      to_listening.extend(listening.captured("notes"));
+      to_connecting.extend(connecting.captured("notes"));
+      std::thread::sleep(TICK);
+    }
+    assert_eq!(to_listening, [note("to listening")]);
+    assert_eq!(to_connecting, [note("to connecting")]);
+
+    connecting.finish();
+    listening.finish();
+    // the listener cleans up its socket once dropped, which happens on the shutdown thread
+    let deadline = Instant::now() + PEER_TIMEOUT;
+    while path.exists() {
+      assert!(Instant::now() < deadline, "the socket file was left behind");
+      std::thread::sleep(TICK);
+    }
+    std::fs::remove_dir_all(&directory).unwrap();
+  }
+
+  #[cfg(unix)]
+  #[test]
+  fn binding_leaves_other_files_alone() {
+    let path = std::env::temp_dir().join(format!("trick-bridge-{}.txt", std::process::id()));
+    std::fs::write(&path, "not a socket").unwrap();
+
+    assert!(
+      BridgeTask::<Note>::listen("notes", "notes bridge", BridgeAddress::Unix(path.clone()))
+        .is_err()
+    );
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "not a socket");
+    std::fs::remove_file(&path).unwrap();
+  }
+}
@@ -1,78 +1,323 @@
-use flume::{Receiver, Sender};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+use flume::{Receiver, Sender, TrySendError};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ChannelError {
+  /// everything on the other side has been dropped
+  Disconnected,
+  /// the channel is at capacity, and its policy is BackpressurePolicy::Error
+  Full,
+}
+
+impl std::fmt::Display for ChannelError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      ChannelError::Disconnected => write!(f, "channel is disconnected"),
+      ChannelError::Full => write!(f, "channel is full"),
+    }
+  }
+}
+
+impl std::error::Error for ChannelError {}
+
+/// what a bounded channel does with a message sent while it's full
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BackpressurePolicy {
+  /// wait until the receiver makes room
+  Block,
+  /// throw away the oldest queued message to make room
+  DropOldest,
+  /// throw away the message being sent
+  DropNewest,
+  /// return ChannelError::Full, the message is thrown away
+  Error,
+}
+
+/// capacity and policy for a channel, None is unbounded
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ChannelConfig {
+  pub capacity: Option<usize>,
+  pub policy: BackpressurePolicy,
+}
+
+impl ChannelConfig {
+  pub const UNBOUNDED: Self = Self {
+    capacity: None,
+    policy: BackpressurePolicy::Block,
+  };
+
+  pub fn bounded(capacity: usize, policy: BackpressurePolicy) -> Self {
+    Self {
+      capacity: Some(capacity),
+      policy,
+    }
+  }
+}
+
+impl Default for ChannelConfig {
+  fn default() -> Self {
+    Self::UNBOUNDED
+  }
+}
+
+/// counters shared by both ends of one direction of a channel
+struct QueueMetrics {
+  config: ChannelConfig,
+  depth: AtomicUsize,
+  sent: AtomicU64,
+  received: AtomicU64,
+  dropped: AtomicU64,
+}
+
+impl QueueMetrics {
+  fn received(&self) {
+    self.received.fetch_add(1, Ordering::Relaxed);
+    self.depth.fetch_sub(1, Ordering::Relaxed);
+  }
+}
+
+/// a snapshot of one direction of a channel, see ChannelRegistry::metrics
+#[derive(Clone, Debug)]
+pub struct ChannelMetrics {
+  pub id: &'static str,
+  pub config: ChannelConfig,
+  /// messages waiting to be received, including any a sender is blocked on
+  pub depth: usize,
+  pub sent: u64,
+  pub received: u64,
+  /// thrown away by DropOldest, DropNewest or Error
+  pub dropped: u64,
+}
+
+impl ChannelMetrics {
+  /// messages received per second, between an earlier snapshot of the same channel and this one
+  pub fn throughput(&self, earlier: &ChannelMetrics, elapsed: std::time::Duration) -> f64 {
+    if elapsed.is_zero() {
+      return 0.0;
+    }
+    self.received.saturating_sub(earlier.received) as f64 / elapsed.as_secs_f64()
+  }
+}
 
-#[derive(Clone)]
 pub struct TaskChannel<T> {
-  sender: Sender<T>,
-  receiver: Receiver<T>,
+  sender: TaskSender<T>,
+  receiver: TaskReceiver<T>,
+}
+
+// derive(Clone) would require T: Clone, which the underlying flume channel doesn't need
+impl<T> Clone for TaskChannel<T> {
+  fn clone(&self) -> Self {
+    Self {
+      sender: self.sender.clone(),
+      receiver: self.receiver.clone(),
+    }
+  }
 }
 
 impl<T: 'static + Send> TaskChannel<T> {
   pub fn new() -> Self {
-    let (sender, receiver) = flume::unbounded();
-    Self { sender, receiver }
+    Self::with_config(ChannelConfig::UNBOUNDED)
+  }
+
+  pub fn bounded(capacity: usize, policy: BackpressurePolicy) -> Self {
+    Self::with_config(ChannelConfig::bounded(capacity, policy))
+  }
+
+  pub fn with_config(config: ChannelConfig) -> Self {
+    let (sender, receiver) = match config.capacity {
+      Some(capacity) => flume::bounded(capacity),
+      None => flume::unbounded(),
+    };
+    let metrics = Arc::new(QueueMetrics {
+      config,
+      depth: AtomicUsize::new(0),
+      sent: AtomicU64::new(0),
+      received: AtomicU64::new(0),
+      dropped: AtomicU64::new(0),
+    });
+
+    // DropOldest makes room by receiving from its own queue
+    let overflow = match config {
+      ChannelConfig {
+        capacity: Some(_),
+        policy: BackpressurePolicy::DropOldest,
+      } => Some(Arc::new(receiver.clone())),
+      _ => None,
+    };
+
+    Self {
+      sender: TaskSender {
+        sender,
+        overflow,
+        metrics: metrics.clone(),
+        tap: None,
+      },
+      receiver: TaskReceiver { receiver, metrics },
+    }
   }
 
   /// Split the TaskChannel into separate Sender/Receiver objects
   pub fn split(self) -> (TaskSender<T>, TaskReceiver<T>) {
-    (
-      TaskSender {
-        sender: self.sender,
-      },
-      TaskReceiver {
-        receiver: self.receiver,
-      },
-    )
+    (self.sender, self.receiver)
   }
 
-  pub fn send(&self, msg: T) -> Result<(), ()> {
-    self.sender.send(msg).map_err(|error| {
-      println!("error: {:?}", error);
-      ()
-    })
+  /// true once the other side has dropped its end
+  pub fn is_disconnected(&self) -> bool {
+    self.receiver.is_disconnected()
+  }
+
+  pub fn send(&self, msg: T) -> Result<(), ChannelError> {
+    self.sender.send(msg)
   }
 
   /// Blocking message recieve
   pub fn recv(&self) -> Option<T> {
-    self.receiver.recv().ok()
+    self.receiver.recv()
   }
 
   pub fn try_recv(&self) -> Option<T> {
-    self.receiver.try_recv().ok()
+    self.receiver.try_recv()
   }
 
   /// Blocking message recieve
   pub async fn recv_async(&self) -> Option<T> {
-    self.receiver.recv_async().await.ok()
+    self.receiver.recv_async().await
   }
 }
 
 /// --------------------------------------------
 /// Decoupled Task SENDER -
 /// --------------------------------------------
-#[derive(Clone)]
 pub struct TaskSender<T> {
   sender: Sender<T>,
+  /// a receiver for this sender's own queue, only for DropOldest.
+  /// shared between clones, so it only counts as one receiver
+  overflow: Option<Arc<Receiver<T>>>,
+  metrics: Arc<QueueMetrics>,
+  /// sees every message before it's sent, see ChannelRegistry::tap
+  tap: Option<SenderTap<T>>,
+}
+
+type SenderTap<T> = Arc<dyn Fn(&T) + Send + Sync>;
+
+// derive(Clone) would require T: Clone, which the underlying flume sender doesn't need
+impl<T> Clone for TaskSender<T> {
+  fn clone(&self) -> Self {
+    Self {
+      sender: self.sender.clone(),
+      overflow: self.overflow.clone(),
+      metrics: self.metrics.clone(),
+      tap: self.tap.clone(),
+    }
+  }
 }
 
 impl<T: Send + 'static> TaskSender<T> {
   pub fn is_disconnected(&self) -> bool {
-    self.sender.is_disconnected()
+    match self.overflow {
+      // the overflow receiver doesn't count as the other side
+      Some(_) => self.sender.receiver_count() <= 1,
+      None => self.sender.is_disconnected(),
+    }
   }
 
-  pub fn send(&self, msg: T) -> Result<(), ()> {
-    self.sender.send(msg).map_err(|error| {
-      println!("error: {:?}", error);
-      ()
-    })
+  pub fn send(&self, msg: T) -> Result<(), ChannelError> {
+    if self.is_disconnected() {
+      logger::error!("{}", ChannelError::Disconnected);
+      return Err(ChannelError::Disconnected);
+    }
+    if let Some(tap) = &self.tap {
+      tap(&msg);
+    }
+
+    // counted before the message is queued, a receiver on another thread can take it
+    // (and count it out) as soon as it's in. a sender blocked on a full queue counts too.
+    self.metrics.depth.fetch_add(1, Ordering::Relaxed);
+    let result = match self.metrics.config {
+      ChannelConfig { capacity: None, .. }
+      | ChannelConfig {
+        policy: BackpressurePolicy::Block,
+        ..
+      } => self
+        .sender
+        .send(msg)
+        .map_err(|_| ChannelError::Disconnected),
+      ChannelConfig {
+        policy: BackpressurePolicy::DropNewest | BackpressurePolicy::Error,
+        ..
+      } => self.sender.try_send(msg).map_err(|error| match error {
+        TrySendError::Full(_) => ChannelError::Full,
+        TrySendError::Disconnected(_) => ChannelError::Disconnected,
+      }),
+      ChannelConfig {
+        policy: BackpressurePolicy::DropOldest,
+        ..
+      } => self.send_dropping_oldest(msg),
+    };
+
+    if result.is_err() {
+      self.metrics.depth.fetch_sub(1, Ordering::Relaxed);
+    }
+    match result {
+      Ok(()) => {
+        self.metrics.sent.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+      }
+      // backpressure doing its job, not worth more than a debug line
+      Err(ChannelError::Full) => {
+        self.metrics.dropped.fetch_add(1, Ordering::Relaxed);
+        logger::debug!("{}, dropped the message", ChannelError::Full);
+        match self.metrics.config.policy {
+          BackpressurePolicy::DropNewest => Ok(()),
+          _ => Err(ChannelError::Full),
+        }
+      }
+      Err(error) => {
+        logger::error!("{}", error);
+        Err(error)
+      }
+    }
+  }
+
+  fn send_dropping_oldest(&self, mut msg: T) -> Result<(), ChannelError> {
+    loop {
+      match self.sender.try_send(msg) {
+        Ok(()) => return Ok(()),
+        Err(TrySendError::Full(returned)) => {
+          msg = returned;
+          // the dropped message was counted into depth when it was sent
+          let dropped = self
+            .overflow
+            .as_ref()
+            .and_then(|queue| queue.try_recv().ok());
+          if dropped.is_some() {
+            self.metrics.dropped.fetch_add(1, Ordering::Relaxed);
+            self.metrics.depth.fetch_sub(1, Ordering::Relaxed);
+          }
+        }
+        Err(TrySendError::Disconnected(_)) => return Err(ChannelError::Disconnected),
+      }
+    }
   }
 }
 
 /// --------------------------------------------
 /// Decoupled Task RECIEVER -
 /// --------------------------------------------
-#[derive(Clone)]
 pub struct TaskReceiver<T> {
   receiver: Receiver<T>,
+  metrics: Arc<QueueMetrics>,
+}
+
+impl<T> Clone for TaskReceiver<T> {
+  fn clone(&self) -> Self {
+    Self {
+      receiver: self.receiver.clone(),
+      metrics: self.metrics.clone(),
+    }
+  }
 }
 
 impl<T: Send + 'static> TaskReceiver<T> {
@@ -82,28 +327,77 @@ impl<T: Send + 'static> TaskReceiver<T> {
 
   /// Blocking receive
   pub fn recv(&self) -> Option<T> {
-    self.receiver.recv().ok()
+    let msg = self.receiver.recv().ok()?;
+    self.metrics.received();
+    Some(msg)
   }
 
   /// Non-blocking receive
   pub fn try_recv(&self) -> Option<T> {
-    self.receiver.try_recv().ok()
+    let msg = self.receiver.try_recv().ok()?;
+    self.metrics.received();
+    Some(msg)
+  }
+
+  /// Blocking receive, gives up once the timeout runs out
+  pub fn recv_timeout(&self, timeout: std::time::Duration) -> Option<T> {
+    let msg = self.receiver.recv_timeout(timeout).ok()?;
+    self.metrics.received();
+    Some(msg)
   }
 
   /// Async receive
   pub async fn recv_async(&self) -> Option<T> {
-    self.receiver.recv_async().await.ok()
+    let msg = self.receiver.recv_async().await.ok()?;
+    self.metrics.received();
+    Some(msg)
   }
 }
 
+fn snapshot_metrics(id: &'static str, metrics: &QueueMetrics) -> ChannelMetrics {
+  ChannelMetrics {
+    id,
+    config: metrics.config,
+    depth: metrics.depth.load(Ordering::Relaxed),
+    sent: metrics.sent.load(Ordering::Relaxed),
+    received: metrics.received.load(Ordering::Relaxed),
+    dropped: metrics.dropped.load(Ordering::Relaxed),
+  }
+}
+
+use std::any::{Any, TypeId};
 use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
+use std::sync::{Mutex, Weak};
+
+use crate::update_manager::container::{DeniedAction, PermissionDenied, TaskId, TaskPermission};
 
 type ChannelId = &'static str;
 
+type Topic<T> = Arc<Mutex<Vec<TaskSender<T>>>>;
+type QueueList = Vec<(ChannelId, Weak<QueueMetrics>)>;
+
 #[derive(Clone)]
 pub struct ChannelRegistry<T> {
   inner: Arc<Mutex<HashMap<ChannelId, PendingChannel<T>>>>,
+  /// subscribers of every topic, see ChannelRegistry::subscribe
+  topics: Arc<Mutex<HashMap<ChannelId, Topic<T>>>>,
+  /// capacities set with ChannelRegistry::configure, everything else is unbounded
+  configs: Arc<Mutex<HashMap<ChannelId, ChannelConfig>>>,
+  /// every queue handed out, dropped once both ends of it are
+  metrics: Arc<Mutex<QueueList>>,
+  /// channels with their own payload type instead of T, see ChannelRegistry::get_or_create_typed
+  typed: Arc<Mutex<HashMap<ChannelId, TypedSlot>>>,
+  /// see ChannelRegistry::tap
+  taps: Arc<Mutex<HashMap<ChannelId, ChannelTap<T>>>>,
+  /// None for the unrestricted registry owned by the UpdateManager
+  access: Option<Arc<ChannelAccess>>,
+}
+
+/// who is using a scoped registry, and where to report what they aren't allowed to do
+struct ChannelAccess {
+  task: TaskId,
+  permission: TaskPermission,
+  denied: TaskSender<PermissionDenied>,
 }
 
 enum PendingChannel<T> {
@@ -111,23 +405,196 @@ enum PendingChannel<T> {
   Pending(TaskChannel<T>),
 }
 
+/// a typed channel's PendingChannel, along with the payload type it was first asked for with
+struct TypedSlot {
+  type_id: TypeId,
+  type_name: &'static str,
+  /// Option<PendingChannel<U>>, None once both sides have their end
+  pending: Box<dyn Any + Send>,
+}
+
+/// a typed channel was asked for with a different payload type than it was created with
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChannelTypeMismatch {
+  pub id: &'static str,
+  pub expected: &'static str,
+  pub found: &'static str,
+}
+
+impl std::fmt::Display for ChannelTypeMismatch {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(
+      f,
+      "channel \"{}\" carries {}, but was asked for with {}",
+      self.id, self.expected, self.found
+    )
+  }
+}
+
+impl std::error::Error for ChannelTypeMismatch {}
+
+/// asks the manager to link a typed channel on the task's behalf, see TaskRequest::LinkTypedChannel.
+/// carries the payload type, so the manager can link it without knowing it.
+#[derive(Clone, Copy)]
+pub struct TypedLink {
+  pub id: &'static str,
+  type_id: fn() -> TypeId,
+  type_name: fn() -> &'static str,
+  link: fn(&TypedChannels<'_>, &'static str) -> Result<Option<AnyChannel>, ChannelTypeMismatch>,
+}
+
+impl TypedLink {
+  /// a link to the channel with ID, carrying U
+  pub const fn of<U: Send + 'static>(id: &'static str) -> Self {
+    Self {
+      id,
+      type_id: TypeId::of::<U>,
+      type_name: std::any::type_name::<U>,
+      link: |channels, id| Ok(channels.get_or_create::<U>(id)?.map(AnyChannel::new)),
+    }
+  }
+
+  pub fn type_name(&self) -> &'static str {
+    (self.type_name)()
+  }
+}
+
+impl PartialEq for TypedLink {
+  fn eq(&self, other: &Self) -> bool {
+    self.id == other.id && (self.type_id)() == (other.type_id)()
+  }
+}
+
+impl std::fmt::Debug for TypedLink {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("TypedLink")
+      .field("id", &self.id)
+      .field("type", &self.type_name())
+      .finish()
+  }
+}
+
+/// a TaskChannel<U> from a TypedLink, handed over through Task::typed_channel_linked.
+/// get the channel back out with downcast.
+pub struct AnyChannel {
+  channel: Box<dyn Any + Send>,
+  clone: fn(&(dyn Any + Send)) -> Box<dyn Any + Send>,
+}
+
+impl AnyChannel {
+  pub fn new<U: Send + 'static>(channel: TaskChannel<U>) -> Self {
+    Self {
+      channel: Box::new(channel),
+      clone: |channel| match channel.downcast_ref::<TaskChannel<U>>() {
+        Some(channel) => Box::new(channel.clone()),
+        None => unreachable!("AnyChannel doesn't hold the channel it was made with"),
+      },
+    }
+  }
+
+  /// gives the AnyChannel back if it carries something other than U
+  pub fn downcast<U: Send + 'static>(self) -> Result<TaskChannel<U>, Self> {
+    match self.channel.downcast::<TaskChannel<U>>() {
+      Ok(channel) => Ok(*channel),
+      Err(channel) => Err(Self {
+        channel,
+        clone: self.clone,
+      }),
+    }
+  }
+}
+
+impl Clone for AnyChannel {
+  fn clone(&self) -> Self {
+    Self {
+      channel: (self.clone)(&*self.channel),
+      clone: self.clone,
+    }
+  }
+}
+
+/// a message sent on a tapped channel or topic, see ChannelRegistry::tap
+pub struct TappedMessage<'a, T> {
+  pub channel: &'static str,
+  /// published to a topic, instead of sent over a linked channel
+  pub topic: bool,
+  /// the task that sent it, None for the UpdateManager's own registry
+  pub task: Option<TaskId>,
+  pub message: &'a T,
+}
+
+pub type ChannelTap<T> = Arc<dyn Fn(TappedMessage<T>) + Send + Sync>;
+
 impl<T: Clone + Send + 'static> ChannelRegistry<T> {
   pub fn new() -> Self {
     Self {
       inner: Arc::new(Mutex::new(HashMap::new())),
+      topics: Arc::new(Mutex::new(HashMap::new())),
+      configs: Arc::new(Mutex::new(HashMap::new())),
+      metrics: Arc::new(Mutex::new(Vec::new())),
+      typed: Arc::new(Mutex::new(HashMap::new())),
+      taps: Arc::new(Mutex::new(HashMap::new())),
+      access: None,
     }
   }
 
+  /// a view of the same registry, that only links the channels the permission allows.
+  /// denied requests are sent to `denied` instead of being linked.
+  pub fn scoped(
+    &self,
+    task: TaskId,
+    permission: TaskPermission,
+    denied: TaskSender<PermissionDenied>,
+  ) -> Self {
+    Self {
+      inner: self.inner.clone(),
+      topics: self.topics.clone(),
+      configs: self.configs.clone(),
+      metrics: self.metrics.clone(),
+      typed: self.typed.clone(),
+      taps: self.taps.clone(),
+      access: Some(Arc::new(ChannelAccess {
+        task,
+        permission,
+        denied,
+      })),
+    }
+  }
+
+  /// true if the task using this registry may link to the ID, otherwise the denial is reported
+  pub fn check_access(&self, id: &'static str) -> bool {
+    let Some(access) = &self.access else {
+      return true;
+    };
+
+    if access.permission.can_link_channel(id) {
+      return true;
+    }
+
+    let _ = access.denied.send(PermissionDenied {
+      task: access.task,
+      permission: access.permission.clone(),
+      action: DeniedAction::LinkChannel(id),
+    });
+    false
+  }
+
   /// Request or create a channel with the given ID.
   /// - If no other task has requested this ID yet, store it.
   /// - If another task is waiting, link the channels and remove the entry.
+  /// - If the registry is scoped and the permission doesn't allow the ID, report it and return None.
   pub fn get_or_create(&self, id: &'static str) -> Option<TaskChannel<T>> {
+    if !self.check_access(id) {
+      return None;
+    }
+
     let mut map = self.inner.lock().ok()?;
 
     // if the channel was accepted already, stop and return it.
     if let Some(PendingChannel::Pending(_)) = map.get(id) {
       // nested statement so it doesnt remove any Waiting state items.
-      if let Some(PendingChannel::Pending(accepted_channel)) = map.remove(id) {
+      if let Some(PendingChannel::Pending(mut accepted_channel)) = map.remove(id) {
+        accepted_channel.sender.tap = self.sender_tap(id, false);
         return Some(accepted_channel);
       }
     }
@@ -136,7 +603,7 @@ impl<T: Clone + Send + 'static> ChannelRegistry<T> {
 
     if let Some(PendingChannel::Waiting(mut matching_channel)) = map.remove(id) {
       // we got the channel verified, now create a new one, and link them up.
-      let mut new_channel = TaskChannel::new();
+      let mut new_channel = self.create_channel(id);
 
       // swap around the recievers so they get messages from one another
       let bucket = new_channel.receiver;
@@ -144,6 +611,7 @@ impl<T: Clone + Send + 'static> ChannelRegistry<T> {
       matching_channel.receiver = bucket;
 
       // return everything back
+      new_channel.sender.tap = self.sender_tap(id, false);
 
       // add the other channel to the accepted queue
       // (side tangent, the spelling for "queue" is total bullshit, i had to google it for this stupid comment)
@@ -156,10 +624,529 @@ impl<T: Clone + Send + 'static> ChannelRegistry<T> {
       return Some(new_channel);
     } else {
       // First task to request this channel
-      let channel = TaskChannel::new();
+      let channel = self.create_channel(id);
       map.insert(id, PendingChannel::Waiting(channel));
 
       return None;
     }
   }
+
+  /// subscribe to a topic, getting a copy of every message published to it from now on.
+  /// unlike get_or_create, a topic can have any number of publishers and subscribers.
+  /// drop the receiver to unsubscribe.
+  pub fn subscribe(&self, id: &'static str) -> Option<TaskReceiver<T>> {
+    if !self.check_access(id) {
+      return None;
+    }
+
+    let topic = self.topic(id)?;
+    let (sender, receiver) = self.create_channel(id).split();
+    topic.lock().ok()?.push(sender);
+    Some(receiver)
+  }
+
+  /// a handle that publishes to every subscriber of the topic
+  pub fn publisher(&self, id: &'static str) -> Option<TopicPublisher<T>> {
+    if !self.check_access(id) {
+      return None;
+    }
+
+    Some(TopicPublisher {
+      id,
+      subscribers: self.topic(id)?,
+      tap: self.sender_tap(id, true),
+    })
+  }
+
+  /// calls the tap with every message sent on the channel or published to the topic,
+  /// along with the task that sent it. only channels linked and publishers created afterwards
+  /// are tapped, so tap before the tasks ask for the ID. typed channels can't be tapped.
+  pub fn tap(&self, id: &'static str, tap: ChannelTap<T>) {
+    if let Ok(mut taps) = self.taps.lock() {
+      taps.insert(id, tap);
+    }
+  }
+
+  /// channels already tapped keep their tap until they're dropped
+  pub fn untap(&self, id: &'static str) {
+    if let Ok(mut taps) = self.taps.lock() {
+      taps.remove(id);
+    }
+  }
+
+  /// sets the capacity and policy of the channel or topic.
+  /// only channels created afterwards use it, so configure before the first task asks for the ID.
+  pub fn configure(&self, id: &'static str, config: ChannelConfig) {
+    if let Ok(mut configs) = self.configs.lock() {
+      configs.insert(id, config);
+    }
+  }
+
+  /// one entry for every direction of every linked channel, and for every topic subscriber
+  pub fn metrics(&self) -> Vec<ChannelMetrics> {
+    let Ok(mut metrics) = self.metrics.lock() else {
+      return Vec::new();
+    };
+    metrics.retain(|(_, queue)| queue.strong_count() > 0);
+    metrics
+      .iter()
+      .filter_map(|(id, queue)| Some(snapshot_metrics(id, &*queue.upgrade()?)))
+      .collect()
+  }
+
+  /// same as get_or_create, but for a channel carrying its own payload type instead of T,
+  /// so subsystems and plugins can talk without adding variants to the shared message enum.
+  /// the first caller decides the type, asking for the same ID with another type is an error.
+  /// tasks can also have the manager link it for them with TaskRequest::LinkTypedChannel.
+  pub fn get_or_create_typed<U: Send + 'static>(
+    &self,
+    id: &'static str,
+  ) -> Result<Option<TaskChannel<U>>, ChannelTypeMismatch> {
+    if !self.check_access(id) {
+      return Ok(None);
+    }
+    self.typed_channels().get_or_create(id)
+  }
+
+  /// get_or_create_typed, with the payload type carried by the TypedLink.
+  /// this is how the manager links a TaskRequest::LinkTypedChannel.
+  pub fn link_typed(&self, link: &TypedLink) -> Result<Option<AnyChannel>, ChannelTypeMismatch> {
+    if !self.check_access(link.id) {
+      return Ok(None);
+    }
+    (link.link)(&self.typed_channels(), link.id)
+  }
+
+  fn typed_channels(&self) -> TypedChannels<'_> {
+    TypedChannels {
+      typed: &self.typed,
+      configs: &self.configs,
+      metrics: &self.metrics,
+    }
+  }
+
+  fn sender_tap(&self, id: &'static str, topic: bool) -> Option<SenderTap<T>> {
+    let tap = self.taps.lock().ok()?.get(id)?.clone();
+    let task = self.access.as_ref().map(|access| access.task);
+    Some(Arc::new(move |message: &T| {
+      tap(TappedMessage {
+        channel: id,
+        topic,
+        task,
+        message,
+      })
+    }))
+  }
+
+  fn create_channel<U: Send + 'static>(&self, id: &'static str) -> TaskChannel<U> {
+    self.typed_channels().create_channel(id)
+  }
+
+  fn topic(&self, id: &'static str) -> Option<Topic<T>> {
+    let mut topics = self.topics.lock().ok()?;
+    Some(topics.entry(id).or_default().clone())
+  }
+}
+
+/// the parts of a registry typed channels live in, none of which depend on T,
+/// so a TypedLink can link a channel for any registry
+struct TypedChannels<'a> {
+  typed: &'a Mutex<HashMap<ChannelId, TypedSlot>>,
+  configs: &'a Mutex<HashMap<ChannelId, ChannelConfig>>,
+  metrics: &'a Mutex<QueueList>,
+}
+
+impl TypedChannels<'_> {
+  fn get_or_create<U: Send + 'static>(
+    &self,
+    id: &'static str,
+  ) -> Result<Option<TaskChannel<U>>, ChannelTypeMismatch> {
+    let Ok(mut typed) = self.typed.lock() else {
+      return Ok(None);
+    };
+    let slot = typed.entry(id).or_insert_with(|| TypedSlot {
+      type_id: TypeId::of::<U>(),
+      type_name: std::any::type_name::<U>(),
+      pending: Box::new(None::<PendingChannel<U>>),
+    });
+
+    if slot.type_id != TypeId::of::<U>() {
+      let mismatch = ChannelTypeMismatch {
+        id,
+        expected: slot.type_name,
+        found: std::any::type_name::<U>(),
+      };
+      logger::error!("{}", mismatch);
+      return Err(mismatch);
+    }
+    let Some(pending) = slot.pending.downcast_mut::<Option<PendingChannel<U>>>() else {
+      unreachable!("typed channel slot doesn't match its TypeId");
+    };
+
+    // same steps as get_or_create: pick up the linked end, link to the waiting end, or start waiting
+    let linked = match pending.take() {
+      Some(PendingChannel::Pending(accepted_channel)) => Some(accepted_channel),
+      Some(PendingChannel::Waiting(mut matching_channel)) => {
+        let mut new_channel = self.create_channel(id);
+        std::mem::swap(&mut new_channel.receiver, &mut matching_channel.receiver);
+        *pending = Some(PendingChannel::Pending(matching_channel));
+        Some(new_channel)
+      }
+      None => {
+        *pending = Some(PendingChannel::Waiting(self.create_channel(id)));
+        None
+      }
+    };
+    Ok(linked)
+  }
+
+  fn create_channel<U: Send + 'static>(&self, id: &'static str) -> TaskChannel<U> {
+    let config = match self.configs.lock() {
+      Ok(configs) => configs.get(id).copied().unwrap_or_default(),
+      Err(_) => ChannelConfig::UNBOUNDED,
+    };
+    let channel = TaskChannel::with_config(config);
+    if let Ok(mut metrics) = self.metrics.lock() {
+      // dropped channels would otherwise pile up until someone asks for metrics
+      metrics.retain(|(_, queue)| queue.strong_count() > 0);
+      metrics.push((id, Arc::downgrade(&channel.sender.metrics)));
+    }
+    channel
+  }
+}
+
+pub struct TopicPublisher<T> {
+  id: ChannelId,
+  subscribers: Topic<T>,
+  tap: Option<SenderTap<T>>,
+}
+
+impl<T> Clone for TopicPublisher<T> {
+  fn clone(&self) -> Self {
+    Self {
+      id: self.id,
+      subscribers: self.subscribers.clone(),
+      tap: self.tap.clone(),
+    }
+  }
+}
+
+impl<T: Clone + Send + 'static> TopicPublisher<T> {
+  pub fn id(&self) -> &'static str {
+    self.id
+  }
+
+  /// sends a copy of the message to every subscriber, returning how many got it.
+  /// subscribers that have dropped their receiver are removed.
+  pub fn publish(&self, msg: T) -> usize {
+    if let Some(tap) = &self.tap {
+      tap(&msg);
+    }
+    // a bounded subscriber can block the send, so nobody else should be stuck
+    // waiting on the lock (eg: subscribing) while it does
+    let subscribers = match self.subscribers.lock() {
+      Ok(subscribers) => subscribers.clone(),
+      Err(_) => return 0,
+    };
+    let live: Vec<_> = subscribers
+      .iter()
+      .filter(|subscriber| !subscriber.is_disconnected())
+      .collect();
+
+    let mut delivered = 0;
+    if let Some((last, rest)) = live.split_last() {
+      for subscriber in rest {
+        if subscriber.send(msg.clone()).is_ok() {
+          delivered += 1;
+        }
+      }
+      if last.send(msg).is_ok() {
+        delivered += 1;
+      }
+    }
+
+    // pruned afterwards, subscribers may have dropped their receiver while we were sending
+    if (live.len() < subscribers.len() || delivered < live.len())
+      && let Ok(mut subscribers) = self.subscribers.lock()
+    {
+      subscribers.retain(|subscriber| !subscriber.is_disconnected());
+    }
+    delivered
+  }
+
+  pub fn subscriber_count(&self) -> usize {
+    match self.subscribers.lock() {
+      Ok(subscribers) => subscribers
+        .iter()
+        .filter(|subscriber| !subscriber.is_disconnected())
+        .count(),
+      Err(_) => 0,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::time::Duration;
+
+  use super::*;
+
+  fn metrics_of<T: Clone + Send + 'static>(
+    registry: &ChannelRegistry<T>,
+    id: &str,
+  ) -> Vec<ChannelMetrics> {
+    registry
+      .metrics()
+      .into_iter()
+      .filter(|metrics| metrics.id == id)
+      .collect()
+  }
+
+  #[test]
+  fn block_waits_for_room() {
+    let (sender, receiver) = TaskChannel::bounded(1, BackpressurePolicy::Block).split();
+    sender.send(1).unwrap();
+
+    let blocked = std::thread::spawn(move || sender.send(2));
+    std::thread::sleep(Duration::from_millis(50));
+    assert!(!blocked.is_finished());
+
+    assert_eq!(receiver.recv(), Some(1));
+    assert_eq!(blocked.join().unwrap(), Ok(()));
+    assert_eq!(receiver.try_recv(), Some(2));
+  }
+
+  #[test]
+  fn drop_oldest_keeps_the_newest() {
+    let channel = TaskChannel::bounded(2, BackpressurePolicy::DropOldest);
+    for message in 1..=4 {
+      assert_eq!(channel.send(message), Ok(()));
+    }
+    assert_eq!(channel.try_recv(), Some(3));
+    assert_eq!(channel.try_recv(), Some(4));
+    assert_eq!(channel.try_recv(), None);
+  }
+
+  #[test]
+  fn drop_newest_keeps_the_oldest() {
+    let channel = TaskChannel::bounded(2, BackpressurePolicy::DropNewest);
+    for message in 1..=4 {
+      assert_eq!(channel.send(message), Ok(()));
+    }
+    assert_eq!(channel.try_recv(), Some(1));
+    assert_eq!(channel.try_recv(), Some(2));
+    assert_eq!(channel.try_recv(), None);
+  }
+
+  #[test]
+  fn error_reports_a_full_channel() {
+    let channel = TaskChannel::bounded(1, BackpressurePolicy::Error);
+    assert_eq!(channel.send(1), Ok(()));
+    assert_eq!(channel.send(2), Err(ChannelError::Full));
+    assert_eq!(channel.try_recv(), Some(1));
+    assert_eq!(channel.send(3), Ok(()));
+  }
+
+  #[test]
+  fn metrics_count_every_message() {
+    let registry = ChannelRegistry::<u32>::new();
+    registry.configure(
+      "input",
+      ChannelConfig::bounded(2, BackpressurePolicy::DropNewest),
+    );
+    let waiting = registry.get_or_create("input");
+    let linked = registry.get_or_create("input").unwrap();
+    let other = registry.get_or_create("input").unwrap();
+
+    for message in 0..3 {
+      linked.send(message).unwrap();
+    }
+    other.try_recv().unwrap();
+
+    let metrics = metrics_of(&registry, "input");
+    let sending = metrics.iter().find(|metrics| metrics.sent > 0).unwrap();
+    assert_eq!(sending.config.capacity, Some(2));
+    assert_eq!(sending.sent, 2);
+    assert_eq!(sending.dropped, 1);
+    assert_eq!(sending.received, 1);
+    assert_eq!(sending.depth, 1);
+
+    drop((waiting, linked, other));
+    assert!(metrics_of(&registry, "input").is_empty());
+  }
+
+  #[test]
+  fn depth_never_goes_below_zero() {
+    const MESSAGES: usize = 20_000;
+    let channel = TaskChannel::<usize>::new();
+    let (sender, receiver) = channel.clone().split();
+
+    let receiving = std::thread::spawn(move || {
+      for _ in 0..MESSAGES {
+        receiver.recv().unwrap();
+        // counting a message out before the sender counted it in would wrap around
+        assert!(receiver.metrics.depth.load(Ordering::Relaxed) <= MESSAGES);
+      }
+    });
+    for message in 0..MESSAGES {
+      sender.send(message).unwrap();
+    }
+    receiving.join().unwrap();
+
+    assert_eq!(sender.metrics.depth.load(Ordering::Relaxed), 0);
+    assert_eq!(
+      sender.metrics.received.load(Ordering::Relaxed),
+      MESSAGES as u64
+    );
+  }
+
+  #[test]
+  fn typed_channels_link_with_their_own_type() {
+    let registry = ChannelRegistry::<u32>::new();
+    let link = TypedLink::of::<String>("names");
+    assert!(registry.link_typed(&link).unwrap().is_none());
+
+    let linked = registry
+      .get_or_create_typed::<String>("names")
+      .unwrap()
+      .unwrap();
+    let waiting = registry.link_typed(&link).unwrap().unwrap();
+    let waiting = waiting.downcast::<String>().ok().unwrap();
+    linked.send("hello".to_string()).unwrap();
+    assert_eq!(waiting.try_recv().as_deref(), Some("hello"));
+  }
+
+  #[test]
+  fn typed_channels_reject_another_type() {
+    let registry = ChannelRegistry::<u32>::new();
+    assert!(
+      registry
+        .get_or_create_typed::<String>("names")
+        .unwrap()
+        .is_none()
+    );
+
+    let Err(mismatch) = registry.get_or_create_typed::<u64>("names") else {
+      panic!("linked a String channel as u64");
+    };
+    assert_eq!(mismatch.id, "names");
+    assert_eq!(mismatch.expected, std::any::type_name::<String>());
+    assert_eq!(mismatch.found, std::any::type_name::<u64>());
+    assert!(matches!(
+      registry.link_typed(&TypedLink::of::<u64>("names")),
+      Err(error) if error == mismatch
+    ));
+
+    // the channel is still there for the right type
+    assert!(
+      registry
+        .get_or_create_typed::<String>("names")
+        .unwrap()
+        .is_some()
+    );
+  }
+
+  #[test]
+  fn typed_links_compare_by_id_and_type() {
+    assert_eq!(TypedLink::of::<u32>("a"), TypedLink::of::<u32>("a"));
+    assert_ne!(TypedLink::of::<u32>("a"), TypedLink::of::<u64>("a"));
+    assert_ne!(TypedLink::of::<u32>("a"), TypedLink::of::<u32>("b"));
+  }
+
+  #[test]
+  fn typed_channels_are_not_tapped() {
+    let registry = ChannelRegistry::<u32>::new();
+    let tapped = Arc::new(AtomicUsize::new(0));
+    let counter = tapped.clone();
+    registry.tap(
+      "numbers",
+      Arc::new(move |_: TappedMessage<u32>| {
+        counter.fetch_add(1, Ordering::Relaxed);
+      }),
+    );
+
+    let _waiting = registry.get_or_create_typed::<u32>("numbers").unwrap();
+    let typed = registry
+      .get_or_create_typed::<u32>("numbers")
+      .unwrap()
+      .unwrap();
+    typed.send(1).unwrap();
+    assert_eq!(tapped.load(Ordering::Relaxed), 0);
+
+    // the same ID as a plain channel is
+    let _waiting = registry.get_or_create("numbers");
+    let plain = registry.get_or_create("numbers").unwrap();
+    plain.send(1).unwrap();
+    assert_eq!(tapped.load(Ordering::Relaxed), 1);
+  }
+
+  #[test]
+  fn topics_fan_out_to_every_subscriber() {
+    let registry = ChannelRegistry::<u32>::new();
+    let first = registry.subscribe("events").unwrap();
+    let second = registry.subscribe("events").unwrap();
+    let publisher = registry.publisher("events").unwrap();
+    assert_eq!(publisher.subscriber_count(), 2);
+
+    assert_eq!(publisher.publish(7), 2);
+    assert_eq!(first.try_recv(), Some(7));
+    assert_eq!(second.try_recv(), Some(7));
+
+    // subscribers only get what's published after they subscribed
+    let late = registry.subscribe("events").unwrap();
+    assert_eq!(late.try_recv(), None);
+    assert_eq!(publisher.publish(8), 3);
+    assert_eq!(late.try_recv(), Some(8));
+  }
+
+  #[test]
+  fn topics_drop_dead_subscribers() {
+    let registry = ChannelRegistry::<u32>::new();
+    let publisher = registry.publisher("events").unwrap();
+    let kept = registry.subscribe("events").unwrap();
+    let dropped = registry.subscribe("events").unwrap();
+    drop(dropped);
+
+    assert_eq!(publisher.subscriber_count(), 1);
+    assert_eq!(publisher.publish(1), 1);
+    assert_eq!(kept.try_recv(), Some(1));
+    assert_eq!(publisher.subscribers.lock().unwrap().len(), 1);
+
+    drop(kept);
+    assert_eq!(publisher.publish(2), 0);
+    assert!(publisher.subscribers.lock().unwrap().is_empty());
+  }
+
+  #[test]
+  fn publishing_doesnt_hold_the_topic_lock() {
+    let registry = ChannelRegistry::<u32>::new();
+    registry.configure(
+      "events",
+      ChannelConfig::bounded(1, BackpressurePolicy::Block),
+    );
+    let slow = registry.subscribe("events").unwrap();
+    let publisher = registry.publisher("events").unwrap();
+    publisher.publish(1);
+
+    // blocks until the slow subscriber makes room
+    let publishing = std::thread::spawn(move || publisher.publish(2));
+    std::thread::sleep(Duration::from_millis(50));
+    assert!(!publishing.is_finished());
+
+    // subscribing has to get the lock the publisher would be holding
+    let (subscribed, subscribed_receiver) = TaskChannel::new().split();
+    let subscriber_registry = registry.clone();
+    std::thread::spawn(move || {
+      let subscriber = subscriber_registry.subscribe("events");
+      let _ = subscribed.send(subscriber.is_some());
+    });
+    assert_eq!(
+      subscribed_receiver.recv_timeout(Duration::from_secs(1)),
+      Some(true)
+    );
+
+    assert_eq!(slow.recv(), Some(1));
+    assert_eq!(publishing.join().unwrap(), 1);
+    assert_eq!(slow.recv(), Some(2));
+  }
 }
@@ -0,0 +1,128 @@
+use std::io::{Read, Write};
+
+/// the largest frame read_frame accepts, anything bigger is treated as a corrupt stream
+pub const MAX_FRAME_LENGTH: usize = 16 << 20;
+
+/// turns a message into bytes and back, so it can be recorded to a file
+/// or sent to another process. see ByteReader for the decoding side.
+pub trait MessageCodec: Sized {
+  fn encode(&self, out: &mut Vec<u8>) -> anyhow::Result<()>;
+  fn decode(bytes: &[u8]) -> anyhow::Result<Self>;
+}
+
+/// a frame is its length as a little endian u32, followed by that many bytes
+pub fn write_frame(writer: &mut impl Write, frame: &[u8]) -> anyhow::Result<()> {
+  if frame.len() > MAX_FRAME_LENGTH {
+    anyhow::bail!(
+      "frame is {} bytes, the most is {}",
+      frame.len(),
+      MAX_FRAME_LENGTH
+    );
+  }
+  writer.write_all(&(frame.len() as u32).to_le_bytes())?;
+  writer.write_all(frame)?;
+  Ok(())
+}
+
+/// None once the stream ends cleanly, between two frames
+pub fn read_frame(reader: &mut impl Read) -> anyhow::Result<Option<Vec<u8>>> {
+  let mut length = [0u8; 4];
+  let mut filled = 0;
+  while filled < length.len() {
+    match reader.read(&mut length[filled..])? {
+      0 if filled == 0 => return Ok(None),
+      0 => anyhow::bail!("stream ended in the middle of a frame length"),
+      read => filled += read,
+    }
+  }
+
+  let length = u32::from_le_bytes(length) as usize;
+  if length > MAX_FRAME_LENGTH {
+    anyhow::bail!(
+      "frame is {} bytes, the most is {}",
+      length,
+      MAX_FRAME_LENGTH
+    );
+  }
+  let mut frame = vec![0u8; length];
+  reader.read_exact(&mut frame)?;
+  Ok(Some(frame))
+}
+
+pub fn put_u8(out: &mut Vec<u8>, value: u8) {
+  out.push(value);
+}
+
+pub fn put_u32(out: &mut Vec<u8>, value: u32) {
+  out.extend_from_slice(&value.to_le_bytes());
+}
+
+pub fn put_u64(out: &mut Vec<u8>, value: u64) {
+  out.extend_from_slice(&value.to_le_bytes());
+}
+
+/// length prefixed, read back with ByteReader::bytes
+pub fn put_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+  put_u32(out, bytes.len() as u32);
+  out.extend_from_slice(bytes);
+}
+
+pub fn put_str(out: &mut Vec<u8>, string: &str) {
+  put_bytes(out, string.as_bytes());
+}
+
+/// reads back what the put_ functions wrote, in the same order
+pub struct ByteReader<'a> {
+  bytes: &'a [u8],
+}
+
+impl<'a> ByteReader<'a> {
+  pub fn new(bytes: &'a [u8]) -> Self {
+    Self { bytes }
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.bytes.is_empty()
+  }
+
+  pub fn u8(&mut self) -> anyhow::Result<u8> {
+    Ok(self.take(1)?[0])
+  }
+
+  pub fn u32(&mut self) -> anyhow::Result<u32> {
+    let bytes = self.take(4)?;
+    Ok(u32::from_le_bytes(bytes.try_into()?))
+  }
+
+  pub fn u64(&mut self) -> anyhow::Result<u64> {
+    let bytes = self.take(8)?;
+    Ok(u64::from_le_bytes(bytes.try_into()?))
+  }
+
+  pub fn bytes(&mut self) -> anyhow::Result<&'a [u8]> {
+    let length = self.u32()? as usize;
+    self.take(length)
+  }
+
+  pub fn str(&mut self) -> anyhow::Result<&'a str> {
+    Ok(std::str::from_utf8(self.bytes()?)?)
+  }
+
+  /// everything that hasn't been read yet
+  pub fn rest(&mut self) -> &'a [u8] {
+    std::mem::take(&mut self.bytes)
+  }
+
+  fn take(&mut self, length: usize) -> anyhow::Result<&'a [u8]> {
+    if self.bytes.len() < length {
+      anyhow::bail!(
+        "wanted {} more bytes, only {} left",
+        length,
+        self.bytes.len()
+      );
+    }
+    let (taken, rest) = self.bytes.split_at(length);
+    self.bytes = rest;
+    Ok(taken)
+  }
+}
@@ -1,62 +1,202 @@
-use std::sync::{Arc, Mutex};
-use crate::update_manager::{self, TaskTag, channel::ChannelRegistry};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+use anyhow::Context;
+use crate::update_manager::{
+  self, TaskRequest, TaskResult, TaskTag,
+  channel::{AnyChannel, ChannelRegistry, TaskChannel, TypedLink},
+  control::{ManagerHandle, TaskInfo, TaskState},
+  profiler::TaskPhase,
+  snapshot,
+  supervisor::SupervisorState,
+  timestep::{FixedClock, TimeStep},
+  watchdog::{self, Watchdog},
+};
 
-#[derive(Clone, PartialEq)]
+/// handed out by the UpdateManager, unique for every task it has added
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct TaskId(pub u64);
+
+#[derive(Clone, Debug, PartialEq)]
 pub enum TaskPermission {
+  /// can do anything, including shutting down the program
   Root,
+  /// can link to any channel, but can't shut down the program or manage other tasks
+  User,
+  /// can only link to the listed channel IDs
+  Sandboxed(&'static [&'static str]),
+}
+
+impl TaskPermission {
+  pub fn can_request_shutdown(&self) -> bool {
+    matches!(self, TaskPermission::Root)
+  }
+
+  /// adding, stopping and removing other tasks
+  pub fn can_manage_tasks(&self) -> bool {
+    matches!(self, TaskPermission::Root)
+  }
+
+  pub fn can_link_channel(&self, id: &str) -> bool {
+    match self {
+      TaskPermission::Root | TaskPermission::User => true,
+      TaskPermission::Sandboxed(allowed) => allowed.contains(&id),
+    }
+  }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum DeniedAction {
+  RequestShutdown,
+  LinkChannel(&'static str),
+  ManageTasks,
+  /// pausing, stepping or scaling time through a ManagerHandle
+  ControlTime,
+}
+
+/// sent to the UpdateManager whenever a task tries something its permission doesn't allow
+#[derive(Clone, Debug)]
+pub struct PermissionDenied {
+  pub task: TaskId,
+  pub permission: TaskPermission,
+  pub action: DeniedAction,
 }
 
 #[derive(Clone)]
 pub struct TaskContainer<M: Clone + Send + 'static> {
   task: Arc<Mutex<dyn update_manager::Task<M>>>,
+  task_id: TaskId,
   task_label: &'static str,
   tags: &'static [TaskTag],
+  requests: &'static [TaskRequest],
   task_permission: TaskPermission,
+  fixed_clock: Option<FixedClock>,
+  supervisor: SupervisorState,
+  /// scoped to this task's permission, see ChannelRegistry::scoped
+  channel_registry: ChannelRegistry<M>,
+  /// LinkChannel requests still waiting on the other side
+  pending_links: Vec<&'static str>,
+  /// LinkTypedChannel requests still waiting on the other side
+  pending_typed_links: Vec<TypedLink>,
+  /// kept so they can be handed over again after a reload
+  linked_channels: Vec<(&'static str, TaskChannel<M>)>,
+  linked_typed_channels: Vec<(&'static str, AnyChannel)>,
+  /// only for tasks with a TaskRequest::ManagerControl
+  manager_handle: Option<ManagerHandle<M>>,
+  /// stopped through the UpdateManager, end() has been called
+  stopped: bool,
+  watchdog: Watchdog,
+  /// from TaskTag::TimeBudget, None uses the watchdog's default
+  time_budget: Option<Duration>,
 }
 
-// impl Drop for TaskContainer {
-//   fn drop(&mut self) {
-//     let mut error_msg = String::from("failed to drop: ");
-//     error_msg.push_str(&self.task_label);
-//     let error_msg: &str = &error_msg;
-
-//     // executes the tasks "destructor" after unlocking
-//     // while let Ok(mut unlocked_task) = self.task.lock() {
-//     //   unlocked_task.end().expect(error_msg);
-//     // }
-//   }
-// }
-
 impl<M: Clone + Send + 'static> TaskContainer<M> {
   pub fn new<TaskT: update_manager::Task<M> + 'static>(
     mut task: TaskT,
+    task_id: TaskId,
     permissions: TaskPermission,
     channel_registry: ChannelRegistry<M>,
+    manager_handle: ManagerHandle<M>,
+    watchdog: Watchdog,
   ) -> anyhow::Result<Self>
   where
     TaskT: Sized,
   {
-    let mut label = "BLANK TASK LABEL";
-    let mut tags: &'static [TaskTag] = &[];
+    // the label comes from start(), so a task stuck in it goes by its type
+    let _log_scope = logger::enter_task(std::any::type_name::<TaskT>(), None);
+    let watch = watchdog.watch(
+      task_id,
+      std::any::type_name::<TaskT>(),
+      TaskPhase::Start,
+      None,
+    );
+    let started = task.start(channel_registry.clone());
+    drop(watch);
 
-    if let Ok(post_init) = task.start(channel_registry) {
-      label = post_init.name;
-      tags = post_init.tags;
-    }
+    let post_init = started.with_context(|| {
+      format!(
+        "task {} ({:?}) failed to start",
+        std::any::type_name::<TaskT>(),
+        task_id
+      )
+    })?;
+    let label = post_init.name;
+    let tags = post_init.tags;
+    let requests = post_init.requests;
+
+    let fixed_clock = tags.iter().find_map(|tag| match tag {
+      TaskTag::FixedRate(rate_hz) => Some(FixedClock::new(*rate_hz)),
+      _ => None,
+    });
+
+    let restart_policy = tags
+      .iter()
+      .find_map(|tag| match tag {
+        TaskTag::Restart(policy) => Some(*policy),
+        _ => None,
+      })
+      .unwrap_or_default();
+
+    let time_budget = tags.iter().find_map(|tag| match tag {
+      TaskTag::TimeBudget(budget) => Some(*budget),
+      _ => None,
+    });
+
+    // denied links are reported once here, instead of on every poll
+    let pending_links = requests
+      .iter()
+      .filter_map(|request| match request {
+        TaskRequest::LinkChannel(id) => Some(*id),
+        _ => None,
+      })
+      .filter(|id| channel_registry.check_access(id))
+      .collect();
+    let pending_typed_links = requests
+      .iter()
+      .filter_map(|request| match request {
+        TaskRequest::LinkTypedChannel(link) => Some(*link),
+        _ => None,
+      })
+      .filter(|link| channel_registry.check_access(link.id))
+      .collect();
+
+    let manager_handle = if requests.contains(&TaskRequest::ManagerControl) {
+      task.manager_linked(manager_handle.clone());
+      Some(manager_handle)
+    } else {
+      None
+    };
 
     Ok(Self {
       task: Arc::new(Mutex::new(task)),
+      task_id,
       task_label: label,
       tags,
+      requests,
       task_permission: permissions,
+      fixed_clock,
+      supervisor: SupervisorState::new(restart_policy),
+      channel_registry,
+      pending_links,
+      pending_typed_links,
+      linked_channels: Vec::new(),
+      linked_typed_channels: Vec::new(),
+      manager_handle,
+      stopped: false,
+      watchdog,
+      time_budget,
     })
   }
 
+  pub fn get_id(&self) -> TaskId {
+    self.task_id
+  }
+
   pub fn get_permission(&self) -> &TaskPermission {
     return &self.task_permission;
   }
 
-  pub fn get_label(&self) -> &str {
+  pub fn get_label(&self) -> &'static str {
     return self.task_label;
   }
 
@@ -64,18 +204,239 @@ impl<M: Clone + Send + 'static> TaskContainer<M> {
     self.tags
   }
 
-  pub fn reload_task(&self, channel_registry: ChannelRegistry<M>) -> anyhow::Result<()> {
-    let mut task_lock = self.task.lock().unwrap();
-    task_lock.end()?;
-    task_lock.start(channel_registry)?;
+  pub fn get_supervisor(&self) -> &SupervisorState {
+    &self.supervisor
+  }
+
+  pub fn get_supervisor_mut(&mut self) -> &mut SupervisorState {
+    &mut self.supervisor
+  }
+
+  pub fn get_requests(&self) -> &'static [TaskRequest] {
+    self.requests
+  }
+
+  pub fn end_task(&self) -> anyhow::Result<()> {
+    let _watch = self.watch(TaskPhase::End);
+    let _log_scope = logger::enter_task(self.task_label, None);
+    let mut task_lock = self.lock_task();
+    catch_panic(|| task_lock.end())
+  }
+
+  /// ends and starts the task again, carrying its snapshot across if it has one
+  pub fn reload_task(&self) -> anyhow::Result<()> {
+    let _watch = self.watch(TaskPhase::Reload);
+    let _log_scope = logger::enter_task(self.task_label, None);
+    let mut task_lock = self.lock_task();
+    catch_panic(|| {
+      let saved = snapshot::save(&mut *task_lock)?;
+      task_lock.end()?;
+      self.start_locked(&mut *task_lock)?;
+
+      if let Some(saved) = saved {
+        snapshot::restore(&mut *task_lock, &saved)?;
+      }
+      Ok(())
+    })
+  }
+
+  /// a panic while the lock was held leaves it poisoned, the task is still there though.
+  /// the poison is left for run() to notice, it fails the task so it's restarted through its policy.
+  fn lock_task(&self) -> MutexGuard<'_, dyn update_manager::Task<M> + 'static> {
+    self
+      .task
+      .lock()
+      .unwrap_or_else(|poisoned| poisoned.into_inner())
+  }
+
+  fn watch(&self, phase: TaskPhase) -> watchdog::WatchGuard {
+    self
+      .watchdog
+      .watch(self.task_id, self.task_label, phase, self.time_budget)
+  }
+
+  /// starts the task again, handing back everything it was given the first time around
+  fn start_locked(&self, task: &mut dyn update_manager::Task<M>) -> anyhow::Result<()> {
+    task.start(self.channel_registry.clone())?;
+
+    for (id, channel) in &self.linked_channels {
+      task.channel_linked(id, channel.clone());
+    }
+    for (id, channel) in &self.linked_typed_channels {
+      task.typed_channel_linked(id, channel.clone());
+    }
+    if let Some(manager_handle) = &self.manager_handle {
+      task.manager_linked(manager_handle.clone());
+    }
+    Ok(())
+  }
+
+  pub fn stop_task(&mut self) -> anyhow::Result<()> {
+    if self.stopped {
+      return Ok(());
+    }
+    self.stopped = true;
+    self.end_task()
+  }
+
+  pub fn resume_task(&mut self) -> anyhow::Result<()> {
+    if !self.stopped {
+      return Ok(());
+    }
+    let _log_scope = logger::enter_task(self.task_label, None);
+    let mut task_lock = self.lock_task();
+    catch_panic(|| self.start_locked(&mut *task_lock))?;
+    drop(task_lock);
+
+    self.stopped = false;
     Ok(())
   }
 
-  pub fn run(&self) -> update_manager::TaskResult {
-    if let Ok(mut task_lock) = self.task.lock() {
-      return task_lock.update();
+  pub fn is_stopped(&self) -> bool {
+    self.stopped
+  }
+
+  /// stopped, disabled and restarting tasks are skipped
+  pub fn is_runnable(&self) -> bool {
+    !self.stopped && !self.supervisor.is_suspended()
+  }
+
+  pub fn get_info(&self) -> TaskInfo {
+    let state = if self.stopped {
+      TaskState::Stopped
+    } else if self.supervisor.is_disabled() {
+      TaskState::Disabled
+    } else if self.supervisor.is_suspended() {
+      TaskState::Restarting
     } else {
-      return update_manager::TaskResult::ErrFatal("FAILED TO UNLOCK MUTEX");
+      TaskState::Running
+    };
+
+    TaskInfo {
+      id: self.task_id,
+      label: self.task_label,
+      permission: self.task_permission.clone(),
+      state,
     }
   }
+
+  /// polls the registry for the channels the task asked for in PostInit,
+  /// and hands over the ones that have been linked through Task::channel_linked
+  /// and Task::typed_channel_linked
+  pub fn link_pending_channels(&mut self) {
+    if self.pending_links.is_empty() && self.pending_typed_links.is_empty() {
+      return;
+    }
+
+    let mut newly_linked = Vec::new();
+    let channel_registry = &self.channel_registry;
+    self
+      .pending_links
+      .retain(|id| match channel_registry.get_or_create(id) {
+        Some(channel) => {
+          newly_linked.push((*id, channel));
+          false
+        }
+        None => true,
+      });
+
+    let mut newly_typed = Vec::new();
+    self
+      .pending_typed_links
+      .retain(|link| match channel_registry.link_typed(link) {
+        Ok(Some(channel)) => {
+          newly_typed.push((link.id, channel));
+          false
+        }
+        Ok(None) => true,
+        // already logged by the registry, asking again won't change the type
+        Err(_) => false,
+      });
+
+    if newly_linked.is_empty() && newly_typed.is_empty() {
+      return;
+    }
+
+    let _log_scope = logger::enter_task(self.task_label, None);
+    let mut task_lock = self.lock_task();
+    let linked = catch_panic(|| {
+      for (id, channel) in &newly_linked {
+        task_lock.channel_linked(id, channel.clone());
+      }
+      for (id, channel) in &newly_typed {
+        task_lock.typed_channel_linked(id, channel.clone());
+      }
+      Ok(())
+    });
+    if let Err(error) = linked {
+      logger::error!("{:#}", error);
+    }
+    drop(task_lock);
+    self.linked_channels.extend(newly_linked);
+    self.linked_typed_channels.extend(newly_typed);
+  }
+
+  /// returns how many times the task has to update this frame, and the timestep to give it.
+  /// variable rate tasks always update once, fixed rate tasks catch up on the frame time.
+  pub fn advance_clock(&mut self, delta: Duration) -> (u32, TimeStep) {
+    match &mut self.fixed_clock {
+      Some(clock) => (
+        clock.advance(delta),
+        TimeStep {
+          delta: clock.step(),
+          ..TimeStep::default()
+        },
+      ),
+      None => (
+        1,
+        TimeStep {
+          delta,
+          ..TimeStep::default()
+        },
+      ),
+    }
+  }
+
+  /// None for variable rate tasks
+  pub fn fixed_alpha(&self) -> Option<f32> {
+    self.fixed_clock.as_ref().map(|clock| clock.alpha())
+  }
+
+  pub fn run(&self, steps: u32, timestep: TimeStep) -> TaskResult {
+    let _watch = self.watch(TaskPhase::Update);
+    let _log_scope = logger::enter_task(self.task_label, Some(timestep.frame));
+    let mut task_lock = match self.task.lock() {
+      Ok(task_lock) => task_lock,
+      Err(poisoned) => {
+        drop(poisoned);
+        self.task.clear_poison();
+        return TaskResult::ErrPanicked(String::from("its mutex was poisoned by an earlier panic"));
+      }
+    };
+
+    for _ in 0..steps {
+      // caught while the lock is still held, so the panic doesn't poison it
+      let task_result = panic::catch_unwind(AssertUnwindSafe(|| {
+        task_lock.timestep(&timestep);
+        task_lock.update()
+      }));
+      match task_result {
+        Ok(TaskResult::Ok) => {}
+        Ok(task_result) => return task_result,
+        Err(payload) => return TaskResult::ErrPanicked(watchdog::panic_message(&*payload)),
+      }
+    }
+    TaskResult::Ok
+  }
+}
+
+/// turns a panic in the call into an error, so it goes through the restart policy
+fn catch_panic<R>(call: impl FnOnce() -> anyhow::Result<R>) -> anyhow::Result<R> {
+  match panic::catch_unwind(AssertUnwindSafe(call)) {
+    Ok(result) => result,
+    Err(payload) => Err(anyhow::anyhow!(
+      "panicked: {}",
+      watchdog::panic_message(&*payload)
+    )),
+  }
 }
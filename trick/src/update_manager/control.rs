@@ -0,0 +1,138 @@
+use crate::update_manager::{
+  Task,
+  channel::{ChannelError, TaskChannel, TaskReceiver, TaskSender},
+  container::{TaskId, TaskPermission},
+  time::{TimeReader, TimeService},
+};
+
+/// sent to the UpdateManager through a ManagerHandle, and handled at the start of the next frame
+pub enum ManagerMessage<M: Clone + Send + 'static> {
+  /// add a new task, running with the given permission
+  SpawnTask(Box<dyn Task<M>>, TaskPermission),
+  /// call end() on every task with the label, and stop updating them until they're resumed
+  StopTask(String),
+  /// call start() on every stopped task with the label, and start updating them again
+  ResumeTask(String),
+  /// end and remove every task with the label
+  UnloadTask(String),
+  /// reply with every task the manager is holding on to
+  ListTasks(TaskSender<Vec<TaskInfo>>),
+  /// pause, resume, step or scale the manager's time service
+  ControlTime(TimeControl),
+}
+
+/// see the TimeService methods of the same name
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TimeControl {
+  Pause,
+  Resume,
+  Step,
+  SetTimeScale(f32),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TaskState {
+  Running,
+  Stopped,
+  /// failed, and waiting for its restart backoff to run out
+  Restarting,
+  Disabled,
+}
+
+#[derive(Clone, Debug)]
+pub struct TaskInfo {
+  pub id: TaskId,
+  pub label: &'static str,
+  pub permission: TaskPermission,
+  pub state: TaskState,
+}
+
+/// given to tasks that ask for it with TaskRequest::ManagerControl.
+/// everything but list_tasks and reading the time needs a permission that can manage tasks,
+/// denied messages are reported like every other denied action.
+pub struct ManagerHandle<M: Clone + Send + 'static> {
+  task: TaskId,
+  sender: TaskSender<(TaskId, ManagerMessage<M>)>,
+  time: TimeReader,
+}
+
+impl<M: Clone + Send + 'static> Clone for ManagerHandle<M> {
+  fn clone(&self) -> Self {
+    Self {
+      task: self.task,
+      sender: self.sender.clone(),
+      time: self.time.clone(),
+    }
+  }
+}
+
+impl<M: Clone + Send + 'static> ManagerHandle<M> {
+  pub fn new(
+    task: TaskId,
+    sender: TaskSender<(TaskId, ManagerMessage<M>)>,
+    time: TimeService,
+  ) -> Self {
+    Self {
+      task,
+      sender,
+      time: time.reader(),
+    }
+  }
+
+  /// the manager's time, see pause, step and set_time_scale for changing it
+  pub fn time(&self) -> &TimeReader {
+    &self.time
+  }
+
+  pub fn send(&self, message: ManagerMessage<M>) -> Result<(), ChannelError> {
+    self.sender.send((self.task, message))
+  }
+
+  pub fn spawn_task<TaskT: Task<M> + 'static>(
+    &self,
+    task: TaskT,
+    permission: TaskPermission,
+  ) -> Result<(), ChannelError> {
+    self.send(ManagerMessage::SpawnTask(Box::new(task), permission))
+  }
+
+  pub fn stop_task(&self, label: &str) -> Result<(), ChannelError> {
+    self.send(ManagerMessage::StopTask(label.to_string()))
+  }
+
+  pub fn resume_task(&self, label: &str) -> Result<(), ChannelError> {
+    self.send(ManagerMessage::ResumeTask(label.to_string()))
+  }
+
+  pub fn unload_task(&self, label: &str) -> Result<(), ChannelError> {
+    self.send(ManagerMessage::UnloadTask(label.to_string()))
+  }
+
+  /// for pausing the game from a task (eg: an editor). like the other time controls,
+  /// it takes effect from the frame after the manager gets to the message.
+  pub fn pause(&self) -> Result<(), ChannelError> {
+    self.send(ManagerMessage::ControlTime(TimeControl::Pause))
+  }
+
+  pub fn resume(&self) -> Result<(), ChannelError> {
+    self.send(ManagerMessage::ControlTime(TimeControl::Resume))
+  }
+
+  /// runs one frame while paused, see TimeService::step
+  pub fn step(&self) -> Result<(), ChannelError> {
+    self.send(ManagerMessage::ControlTime(TimeControl::Step))
+  }
+
+  pub fn set_time_scale(&self, time_scale: f32) -> Result<(), ChannelError> {
+    self.send(ManagerMessage::ControlTime(TimeControl::SetTimeScale(
+      time_scale,
+    )))
+  }
+
+  /// the list is sent back once the manager gets to the message, on the next frame.
+  pub fn list_tasks(&self) -> Result<TaskReceiver<Vec<TaskInfo>>, ChannelError> {
+    let (reply, receiver) = TaskChannel::new().split();
+    self.send(ManagerMessage::ListTasks(reply))?;
+    Ok(receiver)
+  }
+}
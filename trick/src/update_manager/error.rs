@@ -0,0 +1,122 @@
+use std::sync::Arc;
+
+use crate::update_manager::container::TaskId;
+
+/// why a task failed, with the whole cause chain. cheap to clone, so it can be handed to
+/// every subscriber of UpdateManager::subscribe_errors as well as the restart log.
+/// the manager fills in which task it came from, tasks only have to build the error.
+///
+/// `{}` is the outermost message, `{:#}` is the whole chain, like anyhow::Error.
+#[derive(Clone)]
+pub struct TaskError {
+  error: Arc<anyhow::Error>,
+  task: Option<(TaskId, &'static str)>,
+}
+
+impl TaskError {
+  pub fn new(error: impl Into<anyhow::Error>) -> Self {
+    Self {
+      error: Arc::new(error.into()),
+      task: None,
+    }
+  }
+
+  pub fn msg(message: impl std::fmt::Display + std::fmt::Debug + Send + Sync + 'static) -> Self {
+    Self::new(anyhow::Error::msg(message))
+  }
+
+  /// wraps the error in another layer of context, keeping the task it came from
+  pub fn context(self, context: impl std::fmt::Display + Send + Sync + 'static) -> Self {
+    let error = match Arc::try_unwrap(self.error) {
+      Ok(error) => error.context(context),
+      Err(shared) => anyhow::Error::new(Self {
+        error: shared,
+        task: None,
+      })
+      .context(context),
+    };
+    Self {
+      error: Arc::new(error),
+      task: self.task,
+    }
+  }
+
+  /// does nothing if the error already knows its task
+  pub fn with_task(mut self, task: TaskId, label: &'static str) -> Self {
+    if self.task.is_none() {
+      self.task = Some((task, label));
+    }
+    self
+  }
+
+  pub fn task(&self) -> Option<TaskId> {
+    self.task.map(|(task, _)| task)
+  }
+
+  pub fn label(&self) -> Option<&'static str> {
+    self.task.map(|(_, label)| label)
+  }
+
+  pub fn error(&self) -> &anyhow::Error {
+    &self.error
+  }
+
+  /// the error and everything that caused it, outermost first
+  pub fn chain(&self) -> anyhow::Chain<'_> {
+    self.error.chain()
+  }
+
+  pub fn root_cause(&self) -> &(dyn std::error::Error + 'static) {
+    self.error.root_cause()
+  }
+}
+
+impl std::fmt::Display for TaskError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    std::fmt::Display::fmt(&*self.error, f)
+  }
+}
+
+impl std::fmt::Debug for TaskError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("TaskError")
+      .field("task", &self.task)
+      .field("error", &format_args!("{:#}", self.error))
+      .finish()
+  }
+}
+
+impl std::error::Error for TaskError {
+  fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+    self.error.source()
+  }
+}
+
+/// the same task, and the same messages all the way down the chain.
+/// the underlying error types aren't compared, only how they read.
+impl PartialEq for TaskError {
+  fn eq(&self, other: &Self) -> bool {
+    self.task == other.task
+      && (Arc::ptr_eq(&self.error, &other.error)
+        || format!("{:#}", self.error) == format!("{:#}", other.error))
+  }
+}
+
+impl From<anyhow::Error> for TaskError {
+  fn from(error: anyhow::Error) -> Self {
+    Self::new(error)
+  }
+}
+
+/// so a plain message still reads as TaskResult::ErrFatal("...".into())
+impl From<&'static str> for TaskError {
+  fn from(message: &'static str) -> Self {
+    Self::msg(message)
+  }
+}
+
+impl From<String> for TaskError {
+  fn from(message: String) -> Self {
+    Self::msg(message)
+  }
+}
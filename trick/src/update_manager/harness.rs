@@ -0,0 +1,361 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::update_manager::{
+  Task, TaskResult, UpdateManager, UpdateReturn,
+  channel::TaskChannel,
+  container::{TaskId, TaskPermission},
+  recording::Recording,
+  shutdown::ShutdownReport,
+};
+
+/// what happened during one tick of the harness
+#[derive(Clone, Debug)]
+pub struct TickReport {
+  /// starting at 1
+  pub tick: u64,
+  /// the mocked clock once the tick was done
+  pub clock: Duration,
+  pub update_return: UpdateReturn,
+  /// what every task that updated returned, in update order
+  pub results: Vec<(TaskId, &'static str, TaskResult)>,
+}
+
+impl TickReport {
+  /// None if no task with the label updated this tick
+  pub fn result(&self, label: &str) -> Option<&TaskResult> {
+    self
+      .results
+      .iter()
+      .find(|(_, task_label, _)| *task_label == label)
+      .map(|(_, _, task_result)| task_result)
+  }
+}
+
+/// the harness's end of a channel some task links to
+struct Probe<M> {
+  channel: Option<TaskChannel<M>>,
+  /// injected before the channel was linked, sent as soon as it is
+  outbox: Vec<M>,
+  captured: Vec<M>,
+}
+
+/// runs an UpdateManager without a window or a real clock, for tests and CI.
+/// every tick advances the clock by the same amount, so runs are repeatable,
+/// and messages can be injected into and captured from any channel a task links to.
+///
+/// only the manager's clock is mocked. rpc timeouts, the watchdog's time budgets and
+/// shutdown timeouts still run on wall-clock time, so a task that really blocks still trips them.
+///
+/// ```ignore
+/// let mut harness = Harness::<u32>::new(Duration::from_millis(16))?;
+/// harness.add_task(GameTask::default(), TaskPermission::User)?;
+/// harness.inject("input", 42);
+/// harness.run(10);
+/// assert_eq!(harness.report(3).unwrap().result("game"), Some(&TaskResult::Ok));
+/// ```
+pub struct Harness<M: Clone + Send + 'static> {
+  manager: UpdateManager<M>,
+  tick_length: Duration,
+  ticks: Vec<TickReport>,
+  probes: HashMap<&'static str, Probe<M>>,
+}
+
+impl<M: Clone + Send + 'static> Harness<M> {
+  /// single threaded, so the tasks run in the same order every time
+  pub fn new(tick_length: Duration) -> anyhow::Result<Self> {
+    Ok(Self::with_manager(UpdateManager::new()?, tick_length))
+  }
+
+  pub fn with_manager(manager: UpdateManager<M>, tick_length: Duration) -> Self {
+    Self {
+      manager,
+      tick_length,
+      ticks: Vec::new(),
+      probes: HashMap::new(),
+    }
+  }
+
+  pub fn manager(&self) -> &UpdateManager<M> {
+    &self.manager
+  }
+
+  pub fn manager_mut(&mut self) -> &mut UpdateManager<M> {
+    &mut self.manager
+  }
+
+  pub fn add_task<TaskT: Task<M> + 'static>(
+    &mut self,
+    task: TaskT,
+    perms: TaskPermission,
+  ) -> anyhow::Result<()> {
+    self.manager.add_task(task, perms)?;
+    self.link_probes();
+    Ok(())
+  }
+
+  pub fn set_tick_length(&mut self, tick_length: Duration) {
+    self.tick_length = tick_length;
+  }
+
+  /// links the harness to the channel ID, so messages can be injected and captured through it.
+  /// the task on the other side has to ask for it with TaskRequest::LinkChannel.
+  pub fn probe(&mut self, id: &'static str) {
+    self.probes.entry(id).or_insert_with(|| Probe {
+      channel: None,
+      outbox: Vec::new(),
+      captured: Vec::new(),
+    });
+    self.link_probes();
+  }
+
+  /// sends the message to the task linked to the channel ID, probing it if it isn't already.
+  /// messages injected before the task has linked are sent once it does.
+  pub fn inject(&mut self, id: &'static str, message: M) {
+    self.probe(id);
+    if let Some(probe) = self.probes.get_mut(id) {
+      probe.outbox.push(message);
+    }
+    self.flush_probes();
+  }
+
+  /// every message the tasks have sent on the channel ID since the last call
+  pub fn captured(&mut self, id: &'static str) -> Vec<M> {
+    self.capture_probes();
+    match self.probes.get_mut(id) {
+      Some(probe) => std::mem::take(&mut probe.captured),
+      None => Vec::new(),
+    }
+  }
+
+  pub fn is_linked(&self, id: &'static str) -> bool {
+    matches!(
+      self.probes.get(id),
+      Some(Probe {
+        channel: Some(_),
+        ..
+      })
+    )
+  }
+
+  /// advances the clock by one tick, and updates every task once
+  pub fn tick(&mut self) -> &TickReport {
+    self.link_probes();
+    self.flush_probes();
+
+    let update_return = self.manager.update_tasks_with_delta(self.tick_length);
+
+    self.link_probes();
+    self.capture_probes();
+
+    let results = self
+      .manager
+      .frame_results()
+      .iter()
+      .map(|(task, task_result)| {
+        let label = self.manager.get_label(*task).unwrap_or("");
+        (*task, label, task_result.clone())
+      })
+      .collect();
+
+    self.ticks.push(TickReport {
+      tick: self.ticks.len() as u64 + 1,
+      clock: self.manager.clock(),
+      update_return,
+      results,
+    });
+    &self.ticks[self.ticks.len() - 1]
+  }
+
+  /// runs up to the given number of ticks, stopping early if the manager asks to shut down.
+  /// returns the reports of the ticks that ran.
+  pub fn run(&mut self, ticks: u32) -> &[TickReport] {
+    let first = self.ticks.len();
+    for _ in 0..ticks {
+      if let UpdateReturn::Shutdown = self.tick().update_return {
+        break;
+      }
+    }
+    &self.ticks[first..]
+  }
+
+  /// runs one tick for every tick of the recording, each as long as it was when recorded.
+  /// stops early if the manager asks to shut down. see recording::ReplayTask for the messages.
+  pub fn run_recording(&mut self, recording: &Recording) -> &[TickReport] {
+    let first = self.ticks.len();
+    let tick_length = self.tick_length;
+    for delta in &recording.ticks {
+      self.tick_length = *delta;
+      if let UpdateReturn::Shutdown = self.tick().update_return {
+        break;
+      }
+    }
+    self.tick_length = tick_length;
+    &self.ticks[first..]
+  }
+
+  /// every tick that has run, oldest first
+  pub fn reports(&self) -> &[TickReport] {
+    &self.ticks
+  }
+
+  /// the report for a tick, starting at 1
+  pub fn report(&self, tick: u64) -> Option<&TickReport> {
+    let index = tick.checked_sub(1)?;
+    self.ticks.get(index as usize)
+  }
+
+  /// what the task returned on every tick it updated, with the tick number
+  pub fn results_for(&self, label: &str) -> Vec<(u64, TaskResult)> {
+    self
+      .ticks
+      .iter()
+      .filter_map(|report| {
+        report
+          .result(label)
+          .map(|task_result| (report.tick, task_result.clone()))
+      })
+      .collect()
+  }
+
+  /// shuts the manager down, see UpdateManager::shutdown
+  pub fn finish(mut self) -> ShutdownReport {
+    self.manager.shutdown()
+  }
+
+  fn link_probes(&mut self) {
+    let registry = self.manager.channel_registry();
+    for (id, probe) in self.probes.iter_mut() {
+      if probe.channel.is_none() {
+        probe.channel = registry.get_or_create(id);
+      }
+    }
+  }
+
+  fn flush_probes(&mut self) {
+    for probe in self.probes.values_mut() {
+      let Some(channel) = &probe.channel else {
+        continue;
+      };
+      for message in probe.outbox.drain(..) {
+        let _ = channel.send(message);
+      }
+    }
+  }
+
+  fn capture_probes(&mut self) {
+    for probe in self.probes.values_mut() {
+      let Some(channel) = &probe.channel else {
+        continue;
+      };
+      while let Some(message) = channel.try_recv() {
+        probe.captured.push(message);
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::time::Duration;
+
+  use super::Harness;
+  use crate::update_manager::{
+    PostInit, Task, TaskRequest, TaskResult,
+    channel::{ChannelRegistry, TaskChannel},
+    container::TaskPermission,
+  };
+
+  const TICK: Duration = Duration::from_millis(10);
+
+  const ECHO_REQUESTS: &[TaskRequest] = &[TaskRequest::LinkChannel("echo")];
+
+  /// sends back every message plus one
+  struct Echo {
+    channel: Option<TaskChannel<u32>>,
+  }
+
+  impl Task<u32> for Echo {
+    fn start(&mut self, _: ChannelRegistry<u32>) -> anyhow::Result<PostInit> {
+      Ok(PostInit {
+        name: "echo",
+        tags: &[],
+        requests: ECHO_REQUESTS,
+      })
+    }
+
+    fn update(&mut self) -> TaskResult {
+      if let Some(channel) = &self.channel {
+        while let Some(message) = channel.try_recv() {
+          let _ = channel.send(message + 1);
+        }
+      }
+      TaskResult::Ok
+    }
+
+    fn end(&mut self) -> anyhow::Result<()> {
+      Ok(())
+    }
+
+    fn channel_linked(&mut self, _id: &'static str, channel: TaskChannel<u32>) {
+      self.channel = Some(channel);
+    }
+  }
+
+  #[test]
+  fn ticks_advance_the_clock_and_are_reported() {
+    let mut harness = Harness::<u32>::new(TICK).unwrap();
+    harness
+      .add_task(Echo { channel: None }, TaskPermission::User)
+      .unwrap();
+
+    let clocks: Vec<(u64, Duration)> = harness
+      .run(3)
+      .iter()
+      .map(|report| (report.tick, report.clock))
+      .collect();
+    assert_eq!(clocks, [(1, TICK), (2, TICK * 2), (3, TICK * 3)]);
+
+    harness.set_tick_length(Duration::from_millis(25));
+    assert_eq!(harness.tick().clock, TICK * 3 + Duration::from_millis(25));
+    assert_eq!(
+      harness.manager().clock(),
+      TICK * 3 + Duration::from_millis(25)
+    );
+
+    assert_eq!(harness.reports().len(), 4);
+    assert_eq!(
+      harness.report(2).unwrap().result("echo"),
+      Some(&TaskResult::Ok)
+    );
+    assert!(harness.report(0).is_none() && harness.report(5).is_none());
+    let ticks: Vec<u64> = harness
+      .results_for("echo")
+      .into_iter()
+      .map(|(tick, _)| tick)
+      .collect();
+    assert_eq!(ticks, [1, 2, 3, 4]);
+    assert!(harness.results_for("nobody").is_empty());
+  }
+
+  #[test]
+  fn injected_messages_are_captured_back() {
+    let mut harness = Harness::<u32>::new(TICK).unwrap();
+    // injected before the task is even there, it's sent once the channel links
+    harness.inject("echo", 1);
+    harness
+      .add_task(Echo { channel: None }, TaskPermission::User)
+      .unwrap();
+    harness.inject("echo", 10);
+
+    harness.run(2);
+    assert!(harness.is_linked("echo"));
+    assert_eq!(harness.captured("echo"), [2, 11]);
+
+    harness.tick();
+    assert!(harness.captured("echo").is_empty());
+    harness.inject("echo", 20);
+    harness.tick();
+    assert_eq!(harness.captured("echo"), [21]);
+  }
+}
@@ -0,0 +1,347 @@
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+use anyhow::Context;
+use libloading::Library;
+
+use crate::update_manager::{
+  PostInit, Task, TaskRequest, TaskResult,
+  channel::{ChannelRegistry, TaskChannel},
+  control::ManagerHandle,
+  snapshot::{self, SavedState, Snapshot},
+  timestep::TimeStep,
+};
+
+/// bumped whenever the Task trait changes in a way old libraries can't keep up with
+pub const TASK_ABI_VERSION: u32 = 3;
+/// how often the library's modified time is checked
+pub const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+const CREATE_SYMBOL: &[u8] = b"trick_create_task";
+const ABI_SYMBOL: &[u8] = b"trick_task_abi";
+
+type CreateTask<M> = fn() -> Box<dyn Task<M>>;
+type TaskAbi = fn() -> (u32, &'static str);
+
+/// exports a task from a cdylib, so it can be loaded with UpdateManager::add_dylib_task.
+/// the library has to be built with the same compiler and the same version of trick.
+///
+/// `trick::export_task!(HardwareMessage, GameTask::default());`
+#[macro_export]
+macro_rules! export_task {
+  ($message:ty, $task:expr) => {
+    #[unsafe(no_mangle)]
+    pub fn trick_create_task() -> Box<dyn $crate::update_manager::Task<$message>> {
+      Box::new($task)
+    }
+
+    #[unsafe(no_mangle)]
+    pub fn trick_task_abi() -> (u32, &'static str) {
+      (
+        $crate::update_manager::hot_reload::TASK_ABI_VERSION,
+        std::any::type_name::<$message>(),
+      )
+    }
+  };
+}
+
+/// a copy of the library, loaded from its own path.
+/// the build overwrites the original, and the os hands back the already loaded library
+/// when the same path is opened twice, so every generation gets a fresh copy.
+struct CopiedLibrary {
+  library: Option<Library>,
+  path: PathBuf,
+}
+
+impl Drop for CopiedLibrary {
+  fn drop(&mut self) {
+    // closed first, windows won't remove a loaded library
+    self.library = None;
+    let _ = std::fs::remove_file(&self.path);
+  }
+}
+
+struct LoadedTask<M: Clone + Send + 'static> {
+  // dropped before the library, since its code lives in there
+  task: Box<dyn Task<M>>,
+  _library: CopiedLibrary,
+}
+
+fn load_generation<M: Clone + Send + 'static>(
+  source: &Path,
+  generation: u32,
+) -> anyhow::Result<LoadedTask<M>> {
+  let file_name = source
+    .file_name()
+    .with_context(|| format!("{} isn't a library", source.display()))?;
+  let copy_dir = std::env::temp_dir().join("trick-hot-reload");
+  std::fs::create_dir_all(&copy_dir)?;
+  let copy_path = copy_dir.join(format!(
+    "{}-{}-{}",
+    std::process::id(),
+    generation,
+    file_name.to_string_lossy()
+  ));
+  std::fs::copy(source, &copy_path)
+    .with_context(|| format!("failed to copy {}", source.display()))?;
+
+  let library = unsafe { Library::new(&copy_path) }
+    .with_context(|| format!("failed to load {}", source.display()))?;
+  let library = CopiedLibrary {
+    library: Some(library),
+    path: copy_path,
+  };
+  let Some(loaded) = &library.library else {
+    unreachable!();
+  };
+
+  // both sides are only trusted to agree on the types if they agree on these
+  let abi = unsafe { loaded.get::<TaskAbi>(ABI_SYMBOL) }
+    .with_context(|| format!("{} doesn't use export_task!", source.display()))?;
+  let (version, message) = abi();
+  if version != TASK_ABI_VERSION || message != std::any::type_name::<M>() {
+    return Err(anyhow::anyhow!(
+      "{} was built for task abi {} with {}, expected {} with {}",
+      source.display(),
+      version,
+      message,
+      TASK_ABI_VERSION,
+      std::any::type_name::<M>()
+    ));
+  }
+
+  let create = unsafe { loaded.get::<CreateTask<M>>(CREATE_SYMBOL) }
+    .with_context(|| format!("{} doesn't use export_task!", source.display()))?;
+  let task = create();
+
+  Ok(LoadedTask {
+    task,
+    _library: library,
+  })
+}
+
+/// anything 'static the library hands out is gone once it's unloaded,
+/// so the PostInit is copied onto the heap, and kept for the rest of the program.
+fn leak_post_init(post_init: &PostInit) -> PostInit {
+  let leak_str = |text: &str| -> &'static str { Box::leak(text.to_string().into_boxed_str()) };
+
+  let requests: Vec<TaskRequest> = post_init
+    .requests
+    .iter()
+    .filter_map(|request| match request {
+      TaskRequest::LinkChannel(id) => Some(TaskRequest::LinkChannel(leak_str(id))),
+      // a typed link is code from the library, which can't be copied out of it
+      TaskRequest::LinkTypedChannel(link) => {
+        logger::warn!(
+          "reloadable tasks can't ask for typed channels, ignored the link to \"{}\"",
+          link.id
+        );
+        None
+      }
+      TaskRequest::RunBefore(label) => Some(TaskRequest::RunBefore(leak_str(label))),
+      TaskRequest::RunAfter(label) => Some(TaskRequest::RunAfter(leak_str(label))),
+      TaskRequest::ManagerControl => Some(TaskRequest::ManagerControl),
+    })
+    .collect();
+
+  PostInit {
+    name: leak_str(post_init.name),
+    tags: Box::leak(post_init.tags.to_vec().into_boxed_slice()),
+    requests: Box::leak(requests.into_boxed_slice()),
+  }
+}
+
+/// a task loaded from a shared library, which swaps in the new code whenever the library is rebuilt.
+/// the swap happens at the start of an update: the old task's snapshot is saved, it's ended,
+/// and the new task is started, given its channels back, and has the snapshot restored.
+/// if the new code fails to load or start, the old code keeps running.
+///
+/// the name, tags and requests from the first start() are the ones the manager keeps,
+/// changing them in the new code does nothing until the program is restarted.
+/// messages holding on to 'static data from the library must not outlive a swap.
+pub struct DylibTask<M: Clone + Send + 'static> {
+  source: PathBuf,
+  loaded: LoadedTask<M>,
+  generation: u32,
+  modified: Option<SystemTime>,
+  /// a new modified time is only trusted once it has held for a whole poll, the build may still be writing
+  pending_modified: Option<SystemTime>,
+  last_poll: Instant,
+  post_init: Option<PostInit>,
+  channel_registry: Option<ChannelRegistry<M>>,
+  linked_channels: Vec<(&'static str, TaskChannel<M>)>,
+  manager: Option<ManagerHandle<M>>,
+}
+
+impl<M: Clone + Send + 'static> DylibTask<M> {
+  pub fn load(path: impl Into<PathBuf>) -> anyhow::Result<Self> {
+    let source = path.into();
+    let modified = std::fs::metadata(&source)
+      .and_then(|metadata| metadata.modified())
+      .ok();
+    let loaded = load_generation(&source, 0)?;
+
+    Ok(Self {
+      source,
+      loaded,
+      generation: 0,
+      modified,
+      pending_modified: None,
+      last_poll: Instant::now(),
+      post_init: None,
+      channel_registry: None,
+      linked_channels: Vec::new(),
+      manager: None,
+    })
+  }
+
+  pub fn generation(&self) -> u32 {
+    self.generation
+  }
+
+  fn rebuilt(&mut self) -> bool {
+    if self.last_poll.elapsed() < POLL_INTERVAL {
+      return false;
+    }
+    self.last_poll = Instant::now();
+
+    let modified = std::fs::metadata(&self.source)
+      .and_then(|metadata| metadata.modified())
+      .ok();
+    if modified.is_none() || modified == self.modified {
+      self.pending_modified = None;
+      return false;
+    }
+    if self.pending_modified != modified {
+      self.pending_modified = modified;
+      return false;
+    }
+
+    self.modified = modified;
+    self.pending_modified = None;
+    true
+  }
+
+  /// swaps in the rebuilt library
+  pub fn swap(&mut self) -> anyhow::Result<()> {
+    let registry = self
+      .channel_registry
+      .clone()
+      .context("the task has to be started before it can be swapped")?;
+
+    let mut next = load_generation::<M>(&self.source, self.generation + 1)?;
+
+    let state = snapshot::save(&mut *self.loaded.task)
+      .context("failed to save the old code's snapshot, kept the old code")?;
+    if let Err(error) = self.loaded.task.end() {
+      logger::error!(
+        "old code of {} failed to end: {:#}",
+        self.source.display(),
+        error
+      );
+    }
+
+    let started = start_loaded(
+      &mut *next.task,
+      registry.clone(),
+      &self.linked_channels,
+      &self.manager,
+      state.as_ref(),
+    );
+    if let Err(error) = started {
+      // the old code is still loaded, so it's put back the way it was
+      drop(next);
+      start_loaded(
+        &mut *self.loaded.task,
+        registry,
+        &self.linked_channels,
+        &self.manager,
+        state.as_ref(),
+      )
+      .context("the old code failed to start again")?;
+      return Err(error.context("the new code failed to start, kept the old code"));
+    }
+
+    self.loaded = next;
+    self.generation += 1;
+    Ok(())
+  }
+}
+
+/// starts the task, handing back everything the old code was given
+fn start_loaded<M: Clone + Send + 'static>(
+  task: &mut dyn Task<M>,
+  registry: ChannelRegistry<M>,
+  linked_channels: &[(&'static str, TaskChannel<M>)],
+  manager: &Option<ManagerHandle<M>>,
+  state: Option<&SavedState>,
+) -> anyhow::Result<()> {
+  task.start(registry)?;
+  for (id, channel) in linked_channels {
+    task.channel_linked(id, channel.clone());
+  }
+  if let Some(manager) = manager {
+    task.manager_linked(manager.clone());
+  }
+  if let Some(state) = state {
+    snapshot::restore(task, state)?;
+  }
+  Ok(())
+}
+
+impl<M: Clone + Send + 'static> Task<M> for DylibTask<M> {
+  fn start(&mut self, channel_registry: ChannelRegistry<M>) -> anyhow::Result<PostInit> {
+    self.channel_registry = Some(channel_registry.clone());
+    let post_init = self.loaded.task.start(channel_registry)?;
+    let post_init = self
+      .post_init
+      .get_or_insert_with(|| leak_post_init(&post_init));
+
+    Ok(PostInit {
+      name: post_init.name,
+      tags: post_init.tags,
+      requests: post_init.requests,
+    })
+  }
+
+  fn update(&mut self) -> TaskResult {
+    if self.rebuilt() {
+      match self.swap() {
+        Ok(()) => logger::info!(
+          "hot reloaded {} (generation {})",
+          self.source.display(),
+          self.generation
+        ),
+        Err(error) => logger::error!(
+          "failed to hot reload {}: {:#}",
+          self.source.display(),
+          error
+        ),
+      }
+    }
+    self.loaded.task.update()
+  }
+
+  fn end(&mut self) -> anyhow::Result<()> {
+    self.loaded.task.end()
+  }
+
+  fn timestep(&mut self, timestep: &TimeStep) {
+    self.loaded.task.timestep(timestep)
+  }
+
+  fn channel_linked(&mut self, id: &'static str, channel: TaskChannel<M>) {
+    self.linked_channels.retain(|(linked, _)| *linked != id);
+    self.linked_channels.push((id, channel.clone()));
+    self.loaded.task.channel_linked(id, channel)
+  }
+
+  fn manager_linked(&mut self, manager: ManagerHandle<M>) {
+    self.manager = Some(manager.clone());
+    self.loaded.task.manager_linked(manager)
+  }
+
+  fn snapshot(&mut self) -> Option<&mut dyn Snapshot> {
+    self.loaded.task.snapshot()
+  }
+}
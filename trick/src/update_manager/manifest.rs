@@ -0,0 +1,734 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::Context;
+use serde::Deserialize;
+
+use crate::engine::FramePacing;
+use crate::update_manager::{
+  PostInit, Task, TaskRequest, TaskResult, TaskTag, UpdateManager,
+  channel::{AnyChannel, ChannelRegistry, TaskChannel},
+  container::TaskPermission,
+  control::ManagerHandle,
+  snapshot,
+  supervisor::{Escalation, RestartPolicy},
+  timestep::TimeStep,
+};
+
+/// a program described as a list of tasks, instead of add_task calls. eg:
+///
+/// ```toml
+/// worker_threads = 4
+/// target_fps = 60
+///
+/// [[task]]
+/// kind = "sdl"
+/// permission = "root"
+/// tags = ["main_thread"]
+///
+/// [[task]]
+/// kind = "physics"
+/// permission = { sandboxed = ["world state"] }
+/// fixed_rate = 120
+/// links = ["world state"]
+/// run_after = ["sdl3 desktop task"]
+///
+/// [task.settings]
+/// gravity = -9.8
+/// ```
+///
+/// the kind is looked up in a TaskRegistry, which builds the task from its settings.
+/// everything else is added on top of what the task's own start() returns.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+  /// missing or 0 runs every task on the main thread
+  pub worker_threads: Option<usize>,
+  /// missing or 0 is unlocked, see engine::FramePacing
+  pub target_fps: Option<u32>,
+  #[serde(default, rename = "task")]
+  pub tasks: Vec<TaskEntry>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TaskEntry {
+  /// the name the constructor was registered under
+  pub kind: String,
+  /// replaces the label from start(), so the same kind can be added more than once.
+  /// RunBefore and RunAfter name the new label, including ones other tasks ask for in their
+  /// own start(), the label it had before matches nothing.
+  pub label: Option<String>,
+  pub permission: PermissionEntry,
+  #[serde(default)]
+  pub tags: Vec<TagEntry>,
+  /// TaskTag::FixedRate, in hz
+  pub fixed_rate: Option<u32>,
+  /// TaskTag::TimeBudget
+  pub time_budget_ms: Option<u64>,
+  pub restart: Option<RestartEntry>,
+  /// TaskRequest::LinkChannel for every ID
+  #[serde(default)]
+  pub links: Vec<String>,
+  #[serde(default)]
+  pub run_before: Vec<String>,
+  #[serde(default)]
+  pub run_after: Vec<String>,
+  #[serde(default)]
+  pub manager_control: bool,
+  /// handed to the constructor as is
+  #[serde(default)]
+  pub settings: toml::Table,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PermissionEntry {
+  Root,
+  User,
+  Sandboxed(Vec<String>),
+}
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TagEntry {
+  DropLast,
+  MainThread,
+}
+
+/// anything missing is taken from RestartPolicy::DEFAULT
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RestartEntry {
+  pub max_retries: Option<u32>,
+  pub backoff_ms: Option<u64>,
+  pub max_backoff_ms: Option<u64>,
+  pub reset_after_ms: Option<u64>,
+  pub exhausted: Option<EscalationEntry>,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EscalationEntry {
+  Disable,
+  Escalate,
+}
+
+impl Manifest {
+  pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+    let path = path.as_ref();
+    let text = std::fs::read_to_string(path)
+      .with_context(|| format!("failed to read manifest {}", path.display()))?;
+    Self::parse(&text).with_context(|| format!("in manifest {}", path.display()))
+  }
+
+  pub fn parse(text: &str) -> anyhow::Result<Self> {
+    Ok(toml::from_str(text)?)
+  }
+
+  pub fn pacing(&self) -> FramePacing {
+    match self.target_fps {
+      None | Some(0) => FramePacing::Unlocked,
+      Some(fps) => FramePacing::TargetFps(fps),
+    }
+  }
+
+  /// an empty manager with the manifest's worker threads
+  pub fn new_manager<M: Clone + Send + 'static>(&self) -> anyhow::Result<UpdateManager<M>> {
+    match self.worker_threads {
+      None | Some(0) => UpdateManager::new(),
+      Some(workers) => UpdateManager::new_multithreaded(workers),
+    }
+  }
+
+  /// a manager with every task added, in the order they're listed
+  pub fn build_manager<M: Clone + Send + 'static>(
+    &self,
+    registry: &TaskRegistry<M>,
+  ) -> anyhow::Result<UpdateManager<M>> {
+    let mut manager = self.new_manager()?;
+    self.add_tasks(registry, &mut manager)?;
+    Ok(manager)
+  }
+
+  /// only the kind names and labels are checked before anything is added,
+  /// so a typo or a label used twice is caught up front.
+  /// a task that fails to build or start after that leaves the ones before it added.
+  pub fn add_tasks<M: Clone + Send + 'static>(
+    &self,
+    registry: &TaskRegistry<M>,
+    manager: &mut UpdateManager<M>,
+  ) -> anyhow::Result<()> {
+    if let Some(entry) = self
+      .tasks
+      .iter()
+      .find(|entry| !registry.contains(&entry.kind))
+    {
+      return Err(anyhow::anyhow!(
+        "no task kind \"{}\", the registered kinds are {:?}",
+        entry.kind,
+        registry.kinds().collect::<Vec<_>>()
+      ));
+    }
+
+    // RunBefore and RunAfter couldn't tell them apart
+    let mut labels: Vec<&str> = Vec::new();
+    for label in self.tasks.iter().filter_map(|entry| entry.label.as_deref()) {
+      if labels.contains(&label) {
+        return Err(anyhow::anyhow!(
+          "task label \"{}\" is given to more than one task",
+          label
+        ));
+      }
+      labels.push(label);
+    }
+
+    for entry in &self.tasks {
+      let (task, permission) = registry
+        .build(entry)
+        .with_context(|| format!("failed to build task \"{}\"", entry.kind))?;
+      manager.add_task(task, permission)?;
+    }
+    Ok(())
+  }
+}
+
+/// builds a task from the settings table of its manifest entry
+pub type TaskConstructor<M> =
+  Box<dyn Fn(&toml::Table) -> anyhow::Result<Box<dyn Task<M>>> + Send + Sync>;
+
+/// every kind of task a manifest can name
+pub struct TaskRegistry<M: Clone + Send + 'static> {
+  constructors: HashMap<String, TaskConstructor<M>>,
+}
+
+impl<M: Clone + Send + 'static> Default for TaskRegistry<M> {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl<M: Clone + Send + 'static> TaskRegistry<M> {
+  pub fn new() -> Self {
+    Self {
+      constructors: HashMap::new(),
+    }
+  }
+
+  /// replaces whatever was registered under the kind before
+  pub fn register<TaskT, F>(&mut self, kind: &str, constructor: F)
+  where
+    TaskT: Task<M> + 'static,
+    F: Fn(&toml::Table) -> anyhow::Result<TaskT> + Send + Sync + 'static,
+  {
+    self.constructors.insert(
+      kind.to_string(),
+      Box::new(move |settings| Ok(Box::new(constructor(settings)?))),
+    );
+  }
+
+  /// for tasks that don't take any settings
+  pub fn register_default<TaskT: Task<M> + Default + 'static>(&mut self, kind: &str) {
+    self.register(kind, |_| Ok(TaskT::default()));
+  }
+
+  pub fn contains(&self, kind: &str) -> bool {
+    self.constructors.contains_key(kind)
+  }
+
+  pub fn kinds(&self) -> impl Iterator<Item = &str> {
+    self.constructors.keys().map(String::as_str)
+  }
+
+  pub fn build(&self, entry: &TaskEntry) -> anyhow::Result<(ManifestTask<M>, TaskPermission)> {
+    let constructor = self
+      .constructors
+      .get(&entry.kind)
+      .ok_or_else(|| anyhow::anyhow!("no task kind \"{}\"", entry.kind))?;
+    let task = constructor(&entry.settings)?;
+
+    Ok((ManifestTask::new(task, entry), entry.permission()))
+  }
+}
+
+/// the manager wants them 'static, a manifest is read once per program so they're leaked
+fn leak_str(text: &str) -> &'static str {
+  Box::leak(text.to_string().into_boxed_str())
+}
+
+impl TaskEntry {
+  fn permission(&self) -> TaskPermission {
+    match &self.permission {
+      PermissionEntry::Root => TaskPermission::Root,
+      PermissionEntry::User => TaskPermission::User,
+      PermissionEntry::Sandboxed(ids) => {
+        let ids: Vec<&'static str> = ids.iter().map(|id| leak_str(id)).collect();
+        TaskPermission::Sandboxed(Box::leak(ids.into_boxed_slice()))
+      }
+    }
+  }
+
+  fn tags(&self) -> Vec<TaskTag> {
+    let mut tags: Vec<TaskTag> = self
+      .tags
+      .iter()
+      .map(|tag| match tag {
+        TagEntry::DropLast => TaskTag::DropLast,
+        TagEntry::MainThread => TaskTag::MainThread,
+      })
+      .collect();
+    if let Some(rate_hz) = self.fixed_rate {
+      tags.push(TaskTag::FixedRate(rate_hz));
+    }
+    if let Some(budget) = self.time_budget_ms {
+      tags.push(TaskTag::TimeBudget(Duration::from_millis(budget)));
+    }
+    if let Some(restart) = &self.restart {
+      tags.push(TaskTag::Restart(restart.policy()));
+    }
+    tags
+  }
+
+  fn requests(&self) -> Vec<TaskRequest> {
+    let links = self
+      .links
+      .iter()
+      .map(|id| TaskRequest::LinkChannel(leak_str(id)));
+    let before = self
+      .run_before
+      .iter()
+      .map(|label| TaskRequest::RunBefore(leak_str(label)));
+    let after = self
+      .run_after
+      .iter()
+      .map(|label| TaskRequest::RunAfter(leak_str(label)));
+
+    let mut requests: Vec<TaskRequest> = links.chain(before).chain(after).collect();
+    if self.manager_control {
+      requests.push(TaskRequest::ManagerControl);
+    }
+    requests
+  }
+}
+
+impl RestartEntry {
+  fn policy(&self) -> RestartPolicy {
+    let default = RestartPolicy::DEFAULT;
+    RestartPolicy {
+      max_retries: self.max_retries.unwrap_or(default.max_retries),
+      backoff: self
+        .backoff_ms
+        .map(Duration::from_millis)
+        .unwrap_or(default.backoff),
+      max_backoff: self
+        .max_backoff_ms
+        .map(Duration::from_millis)
+        .unwrap_or(default.max_backoff),
+      reset_after: self
+        .reset_after_ms
+        .map(Duration::from_millis)
+        .unwrap_or(default.reset_after),
+      exhausted: match self.exhausted {
+        Some(EscalationEntry::Disable) => Escalation::Disable,
+        Some(EscalationEntry::Escalate) => Escalation::Escalate,
+        None => default.exhausted,
+      },
+    }
+  }
+}
+
+/// wraps a task built from a manifest, adding the entry's label, tags and requests
+/// to the PostInit its start() returns.
+/// a tag from the manifest replaces the task's own tag of the same kind (eg: a different FixedRate).
+pub struct ManifestTask<M: Clone + Send + 'static> {
+  task: Box<dyn Task<M>>,
+  label: Option<&'static str>,
+  tags: Vec<TaskTag>,
+  requests: Vec<TaskRequest>,
+  /// the task's own PostInit slices, and what they were merged into.
+  /// start() runs again on every reload, this keeps it from leaking each time.
+  merged: Option<MergedPostInit>,
+}
+
+struct MergedPostInit {
+  own_tags: &'static [TaskTag],
+  own_requests: &'static [TaskRequest],
+  tags: &'static [TaskTag],
+  requests: &'static [TaskRequest],
+}
+
+impl<M: Clone + Send + 'static> ManifestTask<M> {
+  pub fn new(task: Box<dyn Task<M>>, entry: &TaskEntry) -> Self {
+    Self {
+      task,
+      label: entry.label.as_deref().map(leak_str),
+      tags: entry.tags(),
+      requests: entry.requests(),
+      merged: None,
+    }
+  }
+
+  fn merge(&mut self, post_init: &PostInit) -> (&'static [TaskTag], &'static [TaskRequest]) {
+    if let Some(merged) = &self.merged
+      && std::ptr::eq(merged.own_tags, post_init.tags)
+      && std::ptr::eq(merged.own_requests, post_init.requests)
+    {
+      return (merged.tags, merged.requests);
+    }
+
+    let same_kind =
+      |a: &TaskTag, b: &TaskTag| std::mem::discriminant(a) == std::mem::discriminant(b);
+    let mut tags: Vec<TaskTag> = post_init
+      .tags
+      .iter()
+      .filter(|own| !self.tags.iter().any(|tag| same_kind(own, tag)))
+      .copied()
+      .collect();
+    tags.extend(self.tags.iter().copied());
+
+    let mut requests = post_init.requests.to_vec();
+    for request in &self.requests {
+      if !requests.contains(request) {
+        requests.push(*request);
+      }
+    }
+
+    let merged = MergedPostInit {
+      own_tags: post_init.tags,
+      own_requests: post_init.requests,
+      tags: Box::leak(tags.into_boxed_slice()),
+      requests: Box::leak(requests.into_boxed_slice()),
+    };
+    let slices = (merged.tags, merged.requests);
+    self.merged = Some(merged);
+    slices
+  }
+}
+
+impl<M: Clone + Send + 'static> Task<M> for ManifestTask<M> {
+  fn start(&mut self, channel_registry: ChannelRegistry<M>) -> anyhow::Result<PostInit> {
+    let post_init = self.task.start(channel_registry)?;
+    let (tags, requests) = self.merge(&post_init);
+
+    Ok(PostInit {
+      name: self.label.unwrap_or(post_init.name),
+      tags,
+      requests,
+    })
+  }
+
+  fn update(&mut self) -> TaskResult {
+    self.task.update()
+  }
+
+  fn end(&mut self) -> anyhow::Result<()> {
+    self.task.end()
+  }
+
+  fn timestep(&mut self, timestep: &TimeStep) {
+    self.task.timestep(timestep)
+  }
+
+  fn channel_linked(&mut self, id: &'static str, channel: TaskChannel<M>) {
+    self.task.channel_linked(id, channel)
+  }
+
+  fn typed_channel_linked(&mut self, id: &'static str, channel: AnyChannel) {
+    self.task.typed_channel_linked(id, channel)
+  }
+
+  fn manager_linked(&mut self, manager: ManagerHandle<M>) {
+    self.task.manager_linked(manager)
+  }
+
+  fn snapshot(&mut self) -> Option<&mut dyn snapshot::Snapshot> {
+    self.task.snapshot()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::update_manager::harness::Harness;
+
+  const TICK: Duration = Duration::from_millis(10);
+
+  /// the example from Manifest's docs
+  const EXAMPLE: &str = r#"
+worker_threads = 4
+target_fps = 60
+
+[[task]]
+kind = "sdl"
+permission = "root"
+tags = ["main_thread"]
+
+[[task]]
+kind = "physics"
+permission = { sandboxed = ["world state"] }
+fixed_rate = 120
+links = ["world state"]
+run_after = ["sdl3 desktop task"]
+
+[task.settings]
+gravity = -9.8
+"#;
+
+  const OWN_TAGS: &[TaskTag] = &[TaskTag::FixedRate(30), TaskTag::DropLast];
+  const OWN_REQUESTS: &[TaskRequest] = &[TaskRequest::LinkChannel("world state")];
+  const AFTER_FIRST: &[TaskRequest] = &[TaskRequest::RunAfter("first")];
+
+  struct Sample {
+    name: &'static str,
+    tags: &'static [TaskTag],
+    requests: &'static [TaskRequest],
+  }
+
+  impl Sample {
+    fn named(name: &'static str) -> Self {
+      Self {
+        name,
+        tags: &[],
+        requests: &[],
+      }
+    }
+  }
+
+  impl Task<u32> for Sample {
+    fn start(&mut self, _: ChannelRegistry<u32>) -> anyhow::Result<PostInit> {
+      Ok(PostInit {
+        name: self.name,
+        tags: self.tags,
+        requests: self.requests,
+      })
+    }
+
+    fn update(&mut self) -> TaskResult {
+      TaskResult::Ok
+    }
+
+    fn end(&mut self) -> anyhow::Result<()> {
+      Ok(())
+    }
+  }
+
+  fn registry() -> TaskRegistry<u32> {
+    let mut registry = TaskRegistry::new();
+    registry.register("sdl", |_| Ok(Sample::named("sdl3 desktop task")));
+    registry.register("physics", |settings| {
+      settings
+        .get("gravity")
+        .and_then(toml::Value::as_float)
+        .context("physics needs a gravity")?;
+      Ok(Sample::named("physics"))
+    });
+    registry.register("first", |_| Ok(Sample::named("first")));
+    registry.register("second", |_| {
+      Ok(Sample {
+        requests: AFTER_FIRST,
+        ..Sample::named("second")
+      })
+    });
+    registry
+  }
+
+  fn entry(toml: &str) -> TaskEntry {
+    let mut manifest = Manifest::parse(toml).unwrap();
+    manifest.tasks.remove(0)
+  }
+
+  /// the labels of the tasks in the order they updated on the first tick
+  fn update_order(manifest: &str) -> Vec<&'static str> {
+    let manifest = Manifest::parse(manifest).unwrap();
+    let manager = manifest.build_manager(&registry()).unwrap();
+    let mut harness = Harness::with_manager(manager, TICK);
+    let order = harness
+      .tick()
+      .results
+      .iter()
+      .map(|(_, label, _)| *label)
+      .collect();
+    harness.finish();
+    order
+  }
+
+  #[test]
+  fn the_example_parses_and_builds() {
+    let manifest = Manifest::parse(EXAMPLE).unwrap();
+    assert_eq!(manifest.worker_threads, Some(4));
+    assert_eq!(manifest.pacing(), FramePacing::TargetFps(60));
+
+    let physics = &manifest.tasks[1];
+    assert_eq!(physics.kind, "physics");
+    assert_eq!(physics.fixed_rate, Some(120));
+    assert_eq!(physics.links, ["world state"]);
+    assert_eq!(physics.run_after, ["sdl3 desktop task"]);
+    assert_eq!(physics.settings["gravity"].as_float(), Some(-9.8));
+
+    let manager = manifest.build_manager(&registry()).unwrap();
+    let tasks: Vec<(&str, TaskPermission)> = manager
+      .list_tasks()
+      .into_iter()
+      .map(|info| (info.label, info.permission))
+      .collect();
+    assert_eq!(
+      tasks,
+      [
+        ("sdl3 desktop task", TaskPermission::Root),
+        ("physics", TaskPermission::Sandboxed(&["world state"])),
+      ]
+    );
+  }
+
+  #[test]
+  fn permissions_parse_from_a_name_or_a_sandbox() {
+    let permission =
+      |toml: &str| entry(&format!("[[task]]\nkind = \"sdl\"\n{}", toml)).permission();
+    assert_eq!(permission("permission = \"root\""), TaskPermission::Root);
+    assert_eq!(permission("permission = \"user\""), TaskPermission::User);
+    assert_eq!(
+      permission("permission = { sandboxed = [\"input\", \"world state\"] }"),
+      TaskPermission::Sandboxed(&["input", "world state"])
+    );
+    assert_eq!(
+      permission("permission = { sandboxed = [] }"),
+      TaskPermission::Sandboxed(&[])
+    );
+
+    assert!(Manifest::parse("[[task]]\nkind = \"sdl\"\npermission = \"admin\"").is_err());
+    assert!(Manifest::parse("[[task]]\nkind = \"sdl\"").is_err());
+  }
+
+  #[test]
+  fn worker_threads_pick_the_manager() {
+    let workers = |toml: &str| {
+      let manager = Manifest::parse(toml).unwrap().new_manager::<u32>().unwrap();
+      manager
+        .workers
+        .as_ref()
+        .map(|workers| workers.worker_count())
+    };
+    assert_eq!(workers(""), None);
+    assert_eq!(workers("worker_threads = 0"), None);
+    assert_eq!(workers("worker_threads = 3"), Some(3));
+
+    assert_eq!(Manifest::default().pacing(), FramePacing::Unlocked);
+    assert!(Manifest::parse("worker_thread = 3").is_err());
+  }
+
+  #[test]
+  fn unknown_kinds_are_refused_before_anything_is_added() {
+    let manifest = Manifest::parse(
+      r#"
+[[task]]
+kind = "sdl"
+permission = "root"
+
+[[task]]
+kind = "teleporter"
+permission = "user"
+"#,
+    )
+    .unwrap();
+    let mut manager = manifest.new_manager::<u32>().unwrap();
+
+    let error = manifest.add_tasks(&registry(), &mut manager).unwrap_err();
+    assert!(error.to_string().contains("\"teleporter\""));
+    assert!(manager.list_tasks().is_empty());
+  }
+
+  #[test]
+  fn duplicate_labels_are_refused() {
+    let manifest = Manifest::parse(
+      r#"
+[[task]]
+kind = "first"
+label = "twin"
+permission = "user"
+
+[[task]]
+kind = "second"
+label = "twin"
+permission = "user"
+"#,
+    )
+    .unwrap();
+    let mut manager = manifest.new_manager::<u32>().unwrap();
+
+    let error = manifest.add_tasks(&registry(), &mut manager).unwrap_err();
+    assert!(error.to_string().contains("\"twin\""));
+    assert!(manager.list_tasks().is_empty());
+  }
+
+  #[test]
+  fn entries_merge_into_the_tasks_own_post_init() {
+    let entry = entry(
+      r#"
+[[task]]
+kind = "physics"
+label = "renamed"
+permission = "user"
+fixed_rate = 60
+links = ["world state", "input"]
+run_after = ["sdl3 desktop task"]
+manager_control = true
+"#,
+    );
+    let sample = Sample {
+      tags: OWN_TAGS,
+      requests: OWN_REQUESTS,
+      ..Sample::named("physics")
+    };
+    let mut task = ManifestTask::new(Box::new(sample), &entry);
+
+    let post_init = task.start(ChannelRegistry::new()).unwrap();
+    assert_eq!(post_init.name, "renamed");
+    // the manifest's FixedRate replaces the task's own
+    assert_eq!(post_init.tags, [TaskTag::DropLast, TaskTag::FixedRate(60)]);
+    // the link both ask for is only requested once
+    assert_eq!(
+      post_init.requests,
+      [
+        TaskRequest::LinkChannel("world state"),
+        TaskRequest::LinkChannel("input"),
+        TaskRequest::RunAfter("sdl3 desktop task"),
+        TaskRequest::ManagerControl,
+      ]
+    );
+
+    // starting again after a reload hands back the same slices instead of leaking new ones
+    let again = task.start(ChannelRegistry::new()).unwrap();
+    assert!(std::ptr::eq(post_init.tags, again.tags));
+    assert!(std::ptr::eq(post_init.requests, again.requests));
+  }
+
+  #[test]
+  fn renamed_tasks_are_ordered_by_their_new_label() {
+    // second asks to run after "first" in its start(), which no task is called anymore
+    let renamed = r#"
+[[task]]
+kind = "second"
+permission = "user"
+
+[[task]]
+kind = "first"
+label = "renamed"
+permission = "user"
+"#;
+    assert_eq!(update_order(renamed), ["second", "renamed"]);
+
+    let ordered = r#"
+[[task]]
+kind = "second"
+permission = "user"
+run_after = ["renamed"]
+
+[[task]]
+kind = "first"
+label = "renamed"
+permission = "user"
+"#;
+    assert_eq!(update_order(ordered), ["renamed", "second"]);
+  }
+}
@@ -0,0 +1,162 @@
+use crate::update_manager::{TaskRequest, container::TaskContainer};
+
+/// Sorts the tasks by their RunBefore/RunAfter requests, grouped into stages.
+/// every task only depends on tasks in earlier stages, so a stage can be run in parallel.
+/// tasks without any relations keep the order they were added in.
+pub fn sort_tasks<M: Clone + Send + 'static>(
+  tasks: &[TaskContainer<M>],
+) -> anyhow::Result<Vec<Vec<usize>>> {
+  // dependencies[i] holds every task that has to run before task i
+  let mut dependencies: Vec<Vec<usize>> = vec![Vec::new(); tasks.len()];
+
+  for (index, task) in tasks.iter().enumerate() {
+    for request in task.get_requests() {
+      match request {
+        TaskRequest::RunBefore(label) => {
+          for other in find_label(tasks, label) {
+            dependencies[other].push(index);
+          }
+        }
+        TaskRequest::RunAfter(label) => {
+          for other in find_label(tasks, label) {
+            dependencies[index].push(other);
+          }
+        }
+        _ => {}
+      }
+    }
+  }
+
+  let mut stages = Vec::new();
+  let mut scheduled = vec![false; tasks.len()];
+  let mut remaining = tasks.len();
+
+  while remaining > 0 {
+    let stage: Vec<usize> = (0..tasks.len())
+      .filter(|&index| !scheduled[index])
+      .filter(|&index| dependencies[index].iter().all(|&dep| scheduled[dep]))
+      .collect();
+
+    if stage.is_empty() {
+      return Err(anyhow::anyhow!(
+        "task dependency cycle: {}",
+        describe_cycle(tasks, &dependencies, &scheduled)
+      ));
+    }
+
+    for &index in &stage {
+      scheduled[index] = true;
+    }
+    remaining -= stage.len();
+    stages.push(stage);
+  }
+
+  Ok(stages)
+}
+
+fn find_label<M: Clone + Send + 'static>(
+  tasks: &[TaskContainer<M>],
+  label: &'static str,
+) -> impl Iterator<Item = usize> {
+  tasks
+    .iter()
+    .enumerate()
+    .filter(move |(_, task)| task.get_label() == label)
+    .map(|(index, _)| index)
+}
+
+/// every unscheduled task is waiting on another unscheduled task,
+/// so walking backwards through them is guaranteed to loop around eventually.
+fn describe_cycle<M: Clone + Send + 'static>(
+  tasks: &[TaskContainer<M>],
+  dependencies: &[Vec<usize>],
+  scheduled: &[bool],
+) -> String {
+  let Some(mut current) = (0..tasks.len()).find(|&index| !scheduled[index]) else {
+    return String::from("unknown");
+  };
+
+  let mut path: Vec<usize> = Vec::new();
+  while !path.contains(&current) {
+    path.push(current);
+    current = match dependencies[current].iter().find(|&&dep| !scheduled[dep]) {
+      Some(&dep) => dep,
+      None => break,
+    };
+  }
+
+  // only keep the loop itself, and print it in the order the tasks would run
+  let loop_start = path.iter().position(|&index| index == current).unwrap_or(0);
+  let mut cycle: Vec<&str> = path[loop_start..]
+    .iter()
+    .rev()
+    .map(|&index| tasks[index].get_label())
+    .collect();
+  cycle.push(cycle[0]);
+
+  cycle.join(" -> ")
+}
+
+#[cfg(test)]
+mod tests {
+  use std::time::Duration;
+
+  use super::*;
+  use crate::update_manager::{
+    PostInit, Task, TaskResult, channel::ChannelRegistry, container::TaskPermission,
+    harness::Harness,
+  };
+
+  const TICK: Duration = Duration::from_millis(10);
+
+  struct Named {
+    name: &'static str,
+    requests: &'static [TaskRequest],
+  }
+
+  impl Task<u32> for Named {
+    fn start(&mut self, _: ChannelRegistry<u32>) -> anyhow::Result<PostInit> {
+      Ok(PostInit {
+        name: self.name,
+        tags: &[],
+        requests: self.requests,
+      })
+    }
+
+    fn update(&mut self) -> TaskResult {
+      TaskResult::Ok
+    }
+
+    fn end(&mut self) -> anyhow::Result<()> {
+      Ok(())
+    }
+  }
+
+  #[test]
+  fn run_before_and_run_after_order_updates() {
+    let mut harness = Harness::<u32>::new(TICK).unwrap();
+    // added in the opposite order to the one they ask for
+    let tasks = [
+      Named {
+        name: "render",
+        requests: &[TaskRequest::RunAfter("physics")],
+      },
+      Named {
+        name: "physics",
+        requests: &[],
+      },
+      Named {
+        name: "input",
+        requests: &[TaskRequest::RunBefore("physics")],
+      },
+    ];
+    for task in tasks {
+      harness.add_task(task, TaskPermission::User).unwrap();
+    }
+
+    for report in harness.run(3) {
+      let labels: Vec<&str> = report.results.iter().map(|(_, label, _)| *label).collect();
+      assert_eq!(labels, ["input", "physics", "render"]);
+    }
+  }
+}
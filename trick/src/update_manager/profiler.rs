@@ -0,0 +1,456 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::io::Write;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// how many of the latest samples each histogram is built from
+pub const SAMPLE_WINDOW: usize = 240;
+/// the oldest spans are thrown away past this, about a minute of frames for a handful of tasks
+pub const TRACE_LENGTH: usize = 1 << 16;
+/// upper bounds of the histogram buckets, anything slower lands in the last (unbounded) one
+pub const BUCKET_BOUNDS: [Duration; 10] = [
+  Duration::from_micros(100),
+  Duration::from_micros(250),
+  Duration::from_micros(500),
+  Duration::from_millis(1),
+  Duration::from_millis(2),
+  Duration::from_millis(4),
+  Duration::from_millis(8),
+  Duration::from_millis(16),
+  Duration::from_millis(33),
+  Duration::from_millis(66),
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum TaskPhase {
+  Start,
+  Update,
+  Reload,
+  End,
+}
+
+impl TaskPhase {
+  pub fn name(&self) -> &'static str {
+    match self {
+      TaskPhase::Start => "start",
+      TaskPhase::Update => "update",
+      TaskPhase::Reload => "reload",
+      TaskPhase::End => "end",
+    }
+  }
+}
+
+/// one timed call into a task
+#[derive(Clone, Debug)]
+pub struct Span {
+  pub task: &'static str,
+  pub phase: TaskPhase,
+  pub thread: u64,
+  pub thread_name: Arc<str>,
+  /// time since the profiler was created
+  pub start: Duration,
+  pub duration: Duration,
+}
+
+static NEXT_THREAD_ID: AtomicU64 = AtomicU64::new(1);
+
+thread_local! {
+  // std's ThreadId can't be turned into a number on stable, so threads number themselves
+  static CURRENT_THREAD: (u64, Arc<str>) = {
+    let id = NEXT_THREAD_ID.fetch_add(1, Ordering::Relaxed);
+    let name = match std::thread::current().name() {
+      Some(name) => Arc::from(name),
+      None => Arc::from(format!("thread {}", id)),
+    };
+    (id, name)
+  };
+}
+
+/// the name of the calling thread, as it shows up in spans
+pub fn current_thread_name() -> Arc<str> {
+  CURRENT_THREAD.with(|(_, name)| name.clone())
+}
+
+/// runs the call, and times it against the profiler's epoch.
+/// the label isn't always known up front (start() is what hands it out), so it's filled in later.
+pub fn measure<R>(epoch: Instant, phase: TaskPhase, call: impl FnOnce() -> R) -> (R, Span) {
+  let started = Instant::now();
+  let result = call();
+  let duration = started.elapsed();
+
+  let (thread, thread_name) = CURRENT_THREAD.with(|(id, name)| (*id, name.clone()));
+  let span = Span {
+    task: "",
+    phase,
+    thread,
+    thread_name,
+    start: started.saturating_duration_since(epoch),
+    duration,
+  };
+  (result, span)
+}
+
+/// the latest SAMPLE_WINDOW timings for one task and phase
+#[derive(Clone, Debug, Default)]
+pub struct Histogram {
+  samples: VecDeque<Duration>,
+}
+
+impl Histogram {
+  pub fn record(&mut self, sample: Duration) {
+    if self.samples.len() == SAMPLE_WINDOW {
+      self.samples.pop_front();
+    }
+    self.samples.push_back(sample);
+  }
+
+  pub fn len(&self) -> usize {
+    self.samples.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.samples.is_empty()
+  }
+
+  pub fn last(&self) -> Option<Duration> {
+    self.samples.back().copied()
+  }
+
+  pub fn mean(&self) -> Duration {
+    if self.samples.is_empty() {
+      return Duration::ZERO;
+    }
+    self.samples.iter().sum::<Duration>() / self.samples.len() as u32
+  }
+
+  pub fn max(&self) -> Duration {
+    self.samples.iter().max().copied().unwrap_or_default()
+  }
+
+  /// percentile from 0 to 1, nearest rank
+  pub fn percentile(&self, percentile: f32) -> Duration {
+    if self.samples.is_empty() {
+      return Duration::ZERO;
+    }
+    let mut sorted: Vec<Duration> = self.samples.iter().copied().collect();
+    sorted.sort();
+    let rank = (percentile.clamp(0.0, 1.0) * (sorted.len() - 1) as f32).round() as usize;
+    sorted[rank]
+  }
+
+  /// sample counts for every bucket in BUCKET_BOUNDS, plus one for everything slower
+  pub fn buckets(&self) -> [usize; BUCKET_BOUNDS.len() + 1] {
+    let mut buckets = [0; BUCKET_BOUNDS.len() + 1];
+    for sample in &self.samples {
+      let bucket = BUCKET_BOUNDS
+        .iter()
+        .position(|bound| sample <= bound)
+        .unwrap_or(BUCKET_BOUNDS.len());
+      buckets[bucket] += 1;
+    }
+    buckets
+  }
+}
+
+/// timings for every start, update, reload and end the UpdateManager runs.
+/// keeps a rolling histogram per task label and phase, and a timeline of the latest spans.
+pub struct Profiler {
+  epoch: Instant,
+  histograms: HashMap<(&'static str, TaskPhase), Histogram>,
+  trace: VecDeque<Span>,
+}
+
+impl Default for Profiler {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl Profiler {
+  pub fn new() -> Self {
+    Self {
+      epoch: Instant::now(),
+      histograms: HashMap::new(),
+      trace: VecDeque::new(),
+    }
+  }
+
+  /// every span's start is measured from this
+  pub fn epoch(&self) -> Instant {
+    self.epoch
+  }
+
+  pub fn record(&mut self, span: Span) {
+    self
+      .histograms
+      .entry((span.task, span.phase))
+      .or_default()
+      .record(span.duration);
+
+    if self.trace.len() == TRACE_LENGTH {
+      self.trace.pop_front();
+    }
+    self.trace.push_back(span);
+  }
+
+  pub fn histogram(&self, task: &str, phase: TaskPhase) -> Option<&Histogram> {
+    self
+      .histograms
+      .iter()
+      .find(|((label, histogram_phase), _)| *label == task && *histogram_phase == phase)
+      .map(|(_, histogram)| histogram)
+  }
+
+  /// sorted by label, then phase
+  pub fn histograms(&self) -> Vec<(&'static str, TaskPhase, &Histogram)> {
+    let mut histograms: Vec<_> = self
+      .histograms
+      .iter()
+      .map(|((label, phase), histogram)| (*label, *phase, histogram))
+      .collect();
+    histograms.sort_by_key(|(label, phase, _)| (*label, *phase));
+    histograms
+  }
+
+  /// oldest first
+  pub fn trace(&self) -> impl Iterator<Item = &Span> {
+    self.trace.iter()
+  }
+
+  pub fn clear_trace(&mut self) {
+    self.trace.clear();
+  }
+
+  /// writes the timeline in the chrome trace event format,
+  /// which can be opened with chrome://tracing, perfetto or speedscope.
+  pub fn write_chrome_trace(&self, out: &mut impl Write) -> std::io::Result<()> {
+    write!(out, "{{\"displayTimeUnit\":\"ms\",\"traceEvents\":[")?;
+
+    let threads: BTreeMap<u64, &str> = self
+      .trace
+      .iter()
+      .map(|span| (span.thread, &*span.thread_name))
+      .collect();
+
+    let mut first = true;
+    for (thread, name) in threads {
+      if !first {
+        write!(out, ",")?;
+      }
+      first = false;
+      write!(
+        out,
+        "\n{{\"name\":\"thread_name\",\"ph\":\"M\",\"pid\":0,\"tid\":{},\"args\":{{\"name\":\"{}\"}}}}",
+        thread,
+        escape_json(name)
+      )?;
+    }
+
+    for span in &self.trace {
+      if !first {
+        write!(out, ",")?;
+      }
+      first = false;
+      write!(
+        out,
+        "\n{{\"name\":\"{}\",\"cat\":\"{}\",\"ph\":\"X\",\"ts\":{:.3},\"dur\":{:.3},\"pid\":0,\"tid\":{}}}",
+        escape_json(span.task),
+        span.phase.name(),
+        span.start.as_secs_f64() * 1_000_000.0,
+        span.duration.as_secs_f64() * 1_000_000.0,
+        span.thread
+      )?;
+    }
+
+    write!(out, "\n]}}\n")
+  }
+
+  pub fn save_chrome_trace(&self, path: impl AsRef<std::path::Path>) -> anyhow::Result<()> {
+    let file = std::fs::File::create(path.as_ref())?;
+    let mut out = std::io::BufWriter::new(file);
+    self.write_chrome_trace(&mut out)?;
+    out.flush()?;
+    Ok(())
+  }
+}
+
+fn escape_json(text: &str) -> String {
+  let mut escaped = String::with_capacity(text.len());
+  for character in text.chars() {
+    match character {
+      '"' => escaped.push_str("\\\""),
+      '\\' => escaped.push_str("\\\\"),
+      '\n' => escaped.push_str("\\n"),
+      '\r' => escaped.push_str("\\r"),
+      '\t' => escaped.push_str("\\t"),
+      character if (character as u32) < 0x20 => {
+        escaped.push_str(&format!("\\u{:04x}", character as u32));
+      }
+      character => escaped.push(character),
+    }
+  }
+  escaped
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn micros(micros: u64) -> Duration {
+    Duration::from_micros(micros)
+  }
+
+  fn histogram_of(samples: impl IntoIterator<Item = Duration>) -> Histogram {
+    let mut histogram = Histogram::default();
+    for sample in samples {
+      histogram.record(sample);
+    }
+    histogram
+  }
+
+  fn span(
+    task: &'static str,
+    phase: TaskPhase,
+    thread: (u64, &str),
+    start: u64,
+    duration: u64,
+  ) -> Span {
+    Span {
+      task,
+      phase,
+      thread: thread.0,
+      thread_name: Arc::from(thread.1),
+      start: micros(start),
+      duration: micros(duration),
+    }
+  }
+
+  #[test]
+  fn percentiles_use_the_nearest_rank() {
+    // 1ms to 100ms, recorded out of order
+    let histogram = histogram_of((1..=100).rev().map(Duration::from_millis));
+    assert_eq!(histogram.percentile(0.0), Duration::from_millis(1));
+    assert_eq!(histogram.percentile(0.5), Duration::from_millis(51));
+    assert_eq!(histogram.percentile(0.99), Duration::from_millis(99));
+    assert_eq!(histogram.percentile(1.0), Duration::from_millis(100));
+    // out of range percentiles are clamped
+    assert_eq!(histogram.percentile(2.0), Duration::from_millis(100));
+    assert_eq!(histogram.percentile(-1.0), Duration::from_millis(1));
+
+    assert_eq!(histogram.mean(), micros(50_500));
+    assert_eq!(histogram.max(), Duration::from_millis(100));
+    assert_eq!(histogram.last(), Some(Duration::from_millis(1)));
+    assert_eq!(Histogram::default().percentile(0.5), Duration::ZERO);
+  }
+
+  #[test]
+  fn histograms_only_keep_the_sample_window() {
+    let histogram = histogram_of((0..SAMPLE_WINDOW as u64 + 10).map(micros));
+    assert_eq!(histogram.len(), SAMPLE_WINDOW);
+    // the first ten were pushed out
+    assert_eq!(histogram.percentile(0.0), micros(10));
+  }
+
+  #[test]
+  fn buckets_include_their_upper_bound() {
+    let histogram = histogram_of([
+      micros(50),
+      micros(100),
+      micros(101),
+      Duration::from_millis(16),
+      Duration::from_millis(66),
+      Duration::from_millis(67),
+      Duration::from_secs(1),
+    ]);
+    assert_eq!(histogram.buckets(), [2, 1, 0, 0, 0, 0, 0, 1, 0, 1, 2]);
+    assert_eq!(histogram.buckets().iter().sum::<usize>(), histogram.len());
+  }
+
+  #[test]
+  fn profiler_keeps_a_histogram_per_task_and_phase() {
+    let mut profiler = Profiler::default();
+    profiler.record(span("render", TaskPhase::Update, (1, "main"), 0, 300));
+    profiler.record(span("physics", TaskPhase::Update, (1, "main"), 300, 100));
+    profiler.record(span("render", TaskPhase::Update, (1, "main"), 1000, 500));
+    profiler.record(span("render", TaskPhase::Start, (1, "main"), 2000, 50));
+
+    let render = profiler.histogram("render", TaskPhase::Update).unwrap();
+    assert_eq!(render.len(), 2);
+    assert_eq!(render.max(), micros(500));
+    assert!(profiler.histogram("render", TaskPhase::End).is_none());
+
+    let keys: Vec<(&str, TaskPhase)> = profiler
+      .histograms()
+      .into_iter()
+      .map(|(label, phase, _)| (label, phase))
+      .collect();
+    assert_eq!(
+      keys,
+      [
+        ("physics", TaskPhase::Update),
+        ("render", TaskPhase::Start),
+        ("render", TaskPhase::Update),
+      ]
+    );
+    assert_eq!(profiler.trace().count(), 4);
+  }
+
+  #[test]
+  fn chrome_traces_name_threads_then_list_spans() {
+    let mut profiler = Profiler::new();
+    profiler.record(span(
+      "render",
+      TaskPhase::Update,
+      (2, "trick worker 0"),
+      1500,
+      250,
+    ));
+    profiler.record(span("input", TaskPhase::Start, (1, "main"), 0, 1));
+
+    let mut out = Vec::new();
+    profiler.write_chrome_trace(&mut out).unwrap();
+    assert_eq!(
+      String::from_utf8(out).unwrap(),
+      concat!(
+        "{\"displayTimeUnit\":\"ms\",\"traceEvents\":[",
+        "\n{\"name\":\"thread_name\",\"ph\":\"M\",\"pid\":0,\"tid\":1,\"args\":{\"name\":\"main\"}},",
+        "\n{\"name\":\"thread_name\",\"ph\":\"M\",\"pid\":0,\"tid\":2,\"args\":{\"name\":\"trick worker 0\"}},",
+        "\n{\"name\":\"render\",\"cat\":\"update\",\"ph\":\"X\",\"ts\":1500.000,\"dur\":250.000,\"pid\":0,\"tid\":2},",
+        "\n{\"name\":\"input\",\"cat\":\"start\",\"ph\":\"X\",\"ts\":0.000,\"dur\":1.000,\"pid\":0,\"tid\":1}",
+        "\n]}\n",
+      )
+    );
+
+    profiler.clear_trace();
+    let mut out = Vec::new();
+    profiler.write_chrome_trace(&mut out).unwrap();
+    assert_eq!(
+      String::from_utf8(out).unwrap(),
+      "{\"displayTimeUnit\":\"ms\",\"traceEvents\":[\n]}\n"
+    );
+  }
+
+  #[test]
+  fn chrome_traces_escape_labels_and_thread_names() {
+    let mut profiler = Profiler::new();
+    profiler.record(span(
+      "say \"hi\"\n",
+      TaskPhase::Update,
+      (1, "C:\\tab\there\u{1}"),
+      0,
+      1,
+    ));
+
+    let mut out = Vec::new();
+    profiler.write_chrome_trace(&mut out).unwrap();
+    let trace = String::from_utf8(out).unwrap();
+    assert!(trace.contains(r#""args":{"name":"C:\\tab\there\u0001"}"#));
+    assert!(trace.contains(r#"{"name":"say \"hi\"\n","cat":"update""#));
+    // nothing unescaped is left to break the json
+    assert!(!trace.contains('\t') && !trace.contains('\u{1}'));
+
+    assert_eq!(escape_json("plain ünïcode"), "plain ünïcode");
+    assert_eq!(escape_json("\r"), "\\r");
+  }
+}
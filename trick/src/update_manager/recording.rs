@@ -0,0 +1,628 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::update_manager::{
+  PostInit, Task, TaskResult,
+  channel::{ChannelRegistry, ChannelTap, TaskChannel, TappedMessage, TopicPublisher},
+  codec::{self, ByteReader, MessageCodec},
+  container::TaskId,
+};
+
+const MAGIC: &[u8; 8] = b"TRICKREC";
+/// bumped whenever the file layout changes
+pub const RECORDING_VERSION: u32 = 1;
+
+pub const REPLAY_TASK_LABEL: &str = "replay task";
+
+// every frame of a recording starts with one of these
+const TASK_FRAME: u8 = 0;
+const TICK_FRAME: u8 = 1;
+const MESSAGE_FRAME: u8 = 2;
+
+/// stands in for None in a message frame's task
+const NO_TASK: u64 = u64::MAX;
+
+/// one message sent on a recorded channel or topic
+#[derive(Clone, Debug)]
+pub struct RecordedMessage {
+  /// the frame it was sent during, starting at 1. messages sent before the first frame are 0.
+  pub tick: u64,
+  /// since the recording started
+  pub time: Duration,
+  pub channel: String,
+  /// published to a topic, instead of sent over a linked channel
+  pub topic: bool,
+  /// the label of the task that sent it, None if it wasn't sent by a task
+  pub sender: Option<String>,
+  /// as encoded by MessageCodec::encode
+  pub payload: Vec<u8>,
+}
+
+struct RecorderState {
+  /// None once writing has failed, the rest of the session isn't recorded
+  writer: Option<Box<dyn Write + Send>>,
+  tick: u64,
+}
+
+/// writes the messages of tapped channels to a file, along with the tick and time they were sent.
+/// see UpdateManager::record_channel.
+///
+/// the file is a header followed by frames, see codec::write_frame.
+/// it's flushed once per tick, so a crash loses at most the last frame of messages.
+#[derive(Clone)]
+pub struct Recorder {
+  state: Arc<Mutex<RecorderState>>,
+  started: Instant,
+}
+
+impl Recorder {
+  pub fn create(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+    Self::new(BufWriter::new(File::create(path)?))
+  }
+
+  pub fn new(mut writer: impl Write + Send + 'static) -> anyhow::Result<Self> {
+    writer.write_all(MAGIC)?;
+    writer.write_all(&RECORDING_VERSION.to_le_bytes())?;
+    Ok(Self {
+      state: Arc::new(Mutex::new(RecorderState {
+        writer: Some(Box::new(writer)),
+        tick: 0,
+      })),
+      started: Instant::now(),
+    })
+  }
+
+  /// a tap that records every message it sees, see ChannelRegistry::tap
+  pub fn tap<M: MessageCodec + 'static>(&self) -> ChannelTap<M> {
+    let recorder = self.clone();
+    Arc::new(move |tapped: TappedMessage<M>| recorder.record(tapped))
+  }
+
+  /// remembers the label of the task, so its messages can be told apart when replaying
+  pub fn name_task(&self, task: TaskId, label: &str) {
+    let mut frame = Vec::new();
+    codec::put_u8(&mut frame, TASK_FRAME);
+    codec::put_u64(&mut frame, task.0);
+    codec::put_str(&mut frame, label);
+    self.write(&frame);
+  }
+
+  /// called by the UpdateManager at the start of every frame
+  pub fn next_tick(&self, delta: Duration) {
+    let Ok(mut state) = self.state.lock() else {
+      return;
+    };
+    state.tick += 1;
+
+    let mut frame = Vec::new();
+    codec::put_u8(&mut frame, TICK_FRAME);
+    codec::put_u64(&mut frame, state.tick);
+    codec::put_u64(&mut frame, delta.as_nanos() as u64);
+    Self::write_locked(&mut state, &frame);
+    if let Some(writer) = &mut state.writer
+      && let Err(error) = writer.flush()
+    {
+      logger::error!("failed to flush recording, {}", error);
+      state.writer = None;
+    }
+  }
+
+  pub fn tick(&self) -> u64 {
+    match self.state.lock() {
+      Ok(state) => state.tick,
+      Err(_) => 0,
+    }
+  }
+
+  /// false once writing has failed
+  pub fn is_recording(&self) -> bool {
+    match self.state.lock() {
+      Ok(state) => state.writer.is_some(),
+      Err(_) => false,
+    }
+  }
+
+  pub fn flush(&self) -> anyhow::Result<()> {
+    let Ok(mut state) = self.state.lock() else {
+      anyhow::bail!("recorder mutex is poisoned");
+    };
+    if let Some(writer) = &mut state.writer {
+      writer.flush()?;
+    }
+    Ok(())
+  }
+
+  fn record<M: MessageCodec>(&self, tapped: TappedMessage<M>) {
+    let mut payload = Vec::new();
+    if let Err(error) = tapped.message.encode(&mut payload) {
+      logger::error!(
+        "failed to encode a message on \"{}\" for the recording, {}",
+        tapped.channel, error
+      );
+      return;
+    }
+
+    let Ok(mut state) = self.state.lock() else {
+      return;
+    };
+    let mut frame = Vec::with_capacity(payload.len() + 64);
+    codec::put_u8(&mut frame, MESSAGE_FRAME);
+    codec::put_u64(&mut frame, state.tick);
+    codec::put_u64(&mut frame, self.started.elapsed().as_nanos() as u64);
+    codec::put_str(&mut frame, tapped.channel);
+    codec::put_u8(&mut frame, tapped.topic as u8);
+    codec::put_u64(&mut frame, tapped.task.map_or(NO_TASK, |task| task.0));
+    codec::put_bytes(&mut frame, &payload);
+    Self::write_locked(&mut state, &frame);
+  }
+
+  fn write(&self, frame: &[u8]) {
+    if let Ok(mut state) = self.state.lock() {
+      Self::write_locked(&mut state, frame);
+    }
+  }
+
+  fn write_locked(state: &mut RecorderState, frame: &[u8]) {
+    let Some(writer) = &mut state.writer else {
+      return;
+    };
+    if let Err(error) = codec::write_frame(writer, frame) {
+      logger::error!("failed to write recording, stopping it. {}", error);
+      state.writer = None;
+    }
+  }
+}
+
+/// a session written by a Recorder
+#[derive(Clone, Debug, Default)]
+pub struct Recording {
+  /// the delta of every tick, the first entry is tick 1
+  pub ticks: Vec<Duration>,
+  /// in the order they were sent
+  pub messages: Vec<RecordedMessage>,
+}
+
+impl Recording {
+  pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+    Self::read(BufReader::new(File::open(path)?))
+  }
+
+  /// a recording cut short by a crash is read up to its last whole frame
+  pub fn read(mut reader: impl Read) -> anyhow::Result<Self> {
+    let mut magic = [0u8; 8];
+    reader.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+      anyhow::bail!("not a recording");
+    }
+    let mut version = [0u8; 4];
+    reader.read_exact(&mut version)?;
+    let version = u32::from_le_bytes(version);
+    if version != RECORDING_VERSION {
+      anyhow::bail!(
+        "recording is version {}, expected {}",
+        version,
+        RECORDING_VERSION
+      );
+    }
+
+    let mut recording = Self::default();
+    let mut labels: HashMap<u64, String> = HashMap::new();
+    // task ids of messages sent before their task was named, resolved at the end
+    let mut senders: Vec<u64> = Vec::new();
+
+    loop {
+      let frame = match codec::read_frame(&mut reader) {
+        Ok(Some(frame)) => frame,
+        Ok(None) => break,
+        Err(error) => {
+          logger::warn!("recording ends early, {}", error);
+          break;
+        }
+      };
+
+      let mut frame = ByteReader::new(&frame);
+      match frame.u8()? {
+        TASK_FRAME => {
+          let task = frame.u64()?;
+          labels.insert(task, frame.str()?.to_string());
+        }
+        TICK_FRAME => {
+          let _tick = frame.u64()?;
+          recording.ticks.push(Duration::from_nanos(frame.u64()?));
+        }
+        MESSAGE_FRAME => {
+          let tick = frame.u64()?;
+          let time = Duration::from_nanos(frame.u64()?);
+          let channel = frame.str()?.to_string();
+          let topic = frame.u8()? != 0;
+          senders.push(frame.u64()?);
+          recording.messages.push(RecordedMessage {
+            tick,
+            time,
+            channel,
+            topic,
+            sender: None,
+            payload: frame.bytes()?.to_vec(),
+          });
+        }
+        kind => anyhow::bail!("unknown recording frame {}", kind),
+      }
+    }
+
+    for (message, task) in recording.messages.iter_mut().zip(senders) {
+      message.sender = labels.get(&task).cloned();
+    }
+    Ok(recording)
+  }
+
+  /// every message sent by the task on the channel or topic
+  pub fn messages_from<'a>(
+    &'a self,
+    channel: &'a str,
+    sender: &'a str,
+  ) -> impl Iterator<Item = &'a RecordedMessage> {
+    self.messages.iter().filter(move |message| {
+      message.channel == channel && message.sender.as_deref() == Some(sender)
+    })
+  }
+}
+
+/// sends recorded messages again, on the same tick they were recorded on,
+/// in place of the task that sent them. add it to a headless UpdateManager or Harness
+/// instead of the tasks being replayed, before the tasks that receive the messages.
+///
+/// ```ignore
+/// let recording = Recording::load("session.trickrec")?;
+/// let replay = ReplayTask::new(recording.clone())
+///   .replay(WINDOW_EVENTS_TOPIC, SDL_TASK_LABEL)?;
+/// harness.add_task(replay, TaskPermission::Root)?;
+/// harness.add_task(GameTask::default(), TaskPermission::User)?;
+/// harness.run_recording(&recording);
+/// ```
+pub struct ReplayTask<M: Clone + Send + 'static> {
+  recording: Recording,
+  /// (index in the recording, tick, channel, topic, message), in the order they were sent
+  queued: Vec<(usize, u64, &'static str, bool, M)>,
+  channel_registry: Option<ChannelRegistry<M>>,
+  channels: HashMap<&'static str, TaskChannel<M>>,
+  publishers: HashMap<&'static str, TopicPublisher<M>>,
+  tick: u64,
+}
+
+impl<M: MessageCodec + Clone + Send + 'static> ReplayTask<M> {
+  /// replays nothing until told what to with replay
+  pub fn new(recording: Recording) -> Self {
+    Self {
+      recording,
+      queued: Vec::new(),
+      channel_registry: None,
+      channels: HashMap::new(),
+      publishers: HashMap::new(),
+      tick: 0,
+    }
+  }
+
+  /// replays what the sender task sent on the channel or topic
+  pub fn replay(mut self, channel: &'static str, sender: &str) -> anyhow::Result<Self> {
+    for (index, message) in self.recording.messages.iter().enumerate() {
+      if message.channel != channel || message.sender.as_deref() != Some(sender) {
+        continue;
+      }
+      let decoded = M::decode(&message.payload)?;
+      self
+        .queued
+        .push((index, message.tick, channel, message.topic, decoded));
+    }
+    self.queued.sort_by_key(|(index, ..)| *index);
+    Ok(self)
+  }
+
+  /// true once every message has been sent
+  pub fn is_finished(&self) -> bool {
+    self.queued.is_empty()
+  }
+
+  /// links to channels the same way TaskRequest::LinkChannel does, just later
+  fn link_channels(&mut self) {
+    let Some(channel_registry) = &self.channel_registry else {
+      return;
+    };
+    for (_, _, channel, topic, _) in &self.queued {
+      if *topic {
+        if !self.publishers.contains_key(channel)
+          && let Some(publisher) = channel_registry.publisher(channel)
+        {
+          self.publishers.insert(channel, publisher);
+        }
+      } else if !self.channels.contains_key(channel)
+        && let Some(linked) = channel_registry.get_or_create(channel)
+      {
+        self.channels.insert(channel, linked);
+      }
+    }
+  }
+}
+
+impl<M: MessageCodec + Clone + Send + 'static> Task<M> for ReplayTask<M> {
+  fn start(&mut self, channel_registry: ChannelRegistry<M>) -> anyhow::Result<PostInit> {
+    self.channel_registry = Some(channel_registry);
+    self.tick = 0;
+    self.link_channels();
+    Ok(PostInit {
+      name: REPLAY_TASK_LABEL,
+      tags: &[],
+      requests: &[],
+    })
+  }
+
+  fn update(&mut self) -> TaskResult {
+    self.tick += 1;
+    self.link_channels();
+
+    let tick = self.tick;
+    let channels = &self.channels;
+    let publishers = &self.publishers;
+    // messages on channels that aren't linked yet wait for it, in order
+    let mut waiting: Vec<&'static str> = Vec::new();
+    self
+      .queued
+      .retain(|(_, sent_tick, channel, topic, message)| {
+        if *sent_tick > tick || waiting.contains(channel) {
+          return true;
+        }
+        if *topic {
+          if let Some(publisher) = publishers.get(channel) {
+            publisher.publish(message.clone());
+            return false;
+          }
+        } else if let Some(linked) = channels.get(channel) {
+          let _ = linked.send(message.clone());
+          return false;
+        }
+        waiting.push(channel);
+        true
+      });
+
+    TaskResult::Ok
+  }
+
+  fn end(&mut self) -> anyhow::Result<()> {
+    self.channels.clear();
+    self.publishers.clear();
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::{Arc, Mutex};
+  use std::time::Duration;
+
+  use super::*;
+  use crate::update_manager::{TaskRequest, container::TaskPermission, harness::Harness};
+
+  const TICK: Duration = Duration::from_millis(7);
+
+  #[derive(Clone, Debug, PartialEq)]
+  struct Count(u64);
+
+  impl MessageCodec for Count {
+    fn encode(&self, out: &mut Vec<u8>) -> anyhow::Result<()> {
+      codec::put_u64(out, self.0);
+      Ok(())
+    }
+
+    fn decode(bytes: &[u8]) -> anyhow::Result<Self> {
+      Ok(Count(ByteReader::new(bytes).u64()?))
+    }
+  }
+
+  /// sends its tick on "counts" every other tick, and publishes it to "ticks" every tick
+  #[derive(Default)]
+  struct Counter {
+    tick: u64,
+    channel: Option<TaskChannel<Count>>,
+    publisher: Option<TopicPublisher<Count>>,
+  }
+
+  impl Task<Count> for Counter {
+    fn start(&mut self, channel_registry: ChannelRegistry<Count>) -> anyhow::Result<PostInit> {
+      self.publisher = channel_registry.publisher("ticks");
+      Ok(PostInit {
+        name: "counter",
+        tags: &[],
+        requests: &[TaskRequest::LinkChannel("counts")],
+      })
+    }
+
+    fn channel_linked(&mut self, _: &'static str, channel: TaskChannel<Count>) {
+      self.channel = Some(channel);
+    }
+
+    fn update(&mut self) -> TaskResult {
+      self.tick += 1;
+      if let Some(publisher) = &self.publisher {
+        publisher.publish(Count(self.tick));
+      }
+      if self.tick.is_multiple_of(2)
+        && let Some(channel) = &self.channel
+      {
+        let _ = channel.send(Count(self.tick * 10));
+      }
+      TaskResult::Ok
+    }
+
+    fn end(&mut self) -> anyhow::Result<()> {
+      Ok(())
+    }
+  }
+
+  /// writes down every count it gets, along with its own tick
+  struct Tally {
+    tick: u64,
+    seen: Arc<Mutex<Vec<(u64, Count)>>>,
+    channel: Option<TaskChannel<Count>>,
+    subscription: Option<crate::update_manager::channel::TaskReceiver<Count>>,
+  }
+
+  impl Tally {
+    fn new(seen: Arc<Mutex<Vec<(u64, Count)>>>) -> Self {
+      Self {
+        tick: 0,
+        seen,
+        channel: None,
+        subscription: None,
+      }
+    }
+  }
+
+  impl Task<Count> for Tally {
+    fn start(&mut self, channel_registry: ChannelRegistry<Count>) -> anyhow::Result<PostInit> {
+      self.subscription = channel_registry.subscribe("ticks");
+      Ok(PostInit {
+        name: "tally",
+        tags: &[],
+        requests: &[
+          TaskRequest::LinkChannel("counts"),
+          TaskRequest::RunAfter("counter"),
+        ],
+      })
+    }
+
+    fn channel_linked(&mut self, _: &'static str, channel: TaskChannel<Count>) {
+      self.channel = Some(channel);
+    }
+
+    fn update(&mut self) -> TaskResult {
+      self.tick += 1;
+      let mut seen = self.seen.lock().unwrap();
+      if let Some(subscription) = &self.subscription {
+        while let Some(count) = subscription.try_recv() {
+          seen.push((self.tick, count));
+        }
+      }
+      if let Some(channel) = &self.channel {
+        while let Some(count) = channel.try_recv() {
+          seen.push((self.tick, count));
+        }
+      }
+      TaskResult::Ok
+    }
+
+    fn end(&mut self) -> anyhow::Result<()> {
+      Ok(())
+    }
+  }
+
+  fn recording_path(name: &str) -> std::path::PathBuf {
+    std::env::temp_dir().join(format!("trick-{}-{}.trickrec", name, std::process::id()))
+  }
+
+  /// records a session of Counter and Tally to the path, returning what Tally saw
+  fn record_session(path: &Path, ticks: u32) -> Vec<(u64, Count)> {
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let mut harness = Harness::<Count>::new(TICK).unwrap();
+    let recorder = Recorder::create(path).unwrap();
+    harness.manager_mut().record_channel("counts", &recorder);
+    harness.manager_mut().record_channel("ticks", &recorder);
+    harness
+      .add_task(Counter::default(), TaskPermission::User)
+      .unwrap();
+    harness
+      .add_task(Tally::new(seen.clone()), TaskPermission::User)
+      .unwrap();
+    harness.run(ticks);
+    harness.finish();
+    seen.lock().unwrap().clone()
+  }
+
+  #[test]
+  fn a_recorded_session_replays_the_same() {
+    let path = recording_path("replay");
+    let recorded = record_session(&path, 6);
+    assert!(!recorded.is_empty());
+
+    let recording = Recording::load(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(recording.ticks, [TICK; 6]);
+    // 6 publishes, and a send every other tick
+    assert_eq!(recording.messages_from("ticks", "counter").count(), 6);
+    let counts: Vec<Count> = recording
+      .messages_from("counts", "counter")
+      .map(|message| Count::decode(&message.payload).unwrap())
+      .collect();
+    assert_eq!(counts, [Count(20), Count(40), Count(60)]);
+    assert!(
+      recording
+        .messages
+        .iter()
+        .all(|message| message.tick >= 1 && message.tick <= 6)
+    );
+
+    // the replay stands in for the counter, tally shouldn't be able to tell
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let mut harness = Harness::<Count>::new(Duration::from_secs(1)).unwrap();
+    let replay = ReplayTask::new(recording.clone())
+      .replay("ticks", "counter")
+      .unwrap()
+      .replay("counts", "counter")
+      .unwrap();
+    harness.add_task(replay, TaskPermission::User).unwrap();
+    harness
+      .add_task(Tally::new(seen.clone()), TaskPermission::User)
+      .unwrap();
+    assert_eq!(harness.run_recording(&recording).len(), 6);
+    assert_eq!(harness.manager().clock(), TICK * 6);
+    assert_eq!(*seen.lock().unwrap(), recorded);
+    harness.finish();
+  }
+
+  #[test]
+  fn a_truncated_recording_reads_up_to_its_last_whole_frame() {
+    let path = recording_path("truncated");
+    record_session(&path, 4);
+    let bytes = std::fs::read(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let whole = Recording::read(&bytes[..]).unwrap();
+
+    // cut into the middle of the last frame, as a crash while writing would
+    let cut = Recording::read(&bytes[..bytes.len() - 3]).unwrap();
+    assert_eq!(cut.ticks, whole.ticks);
+    assert_eq!(cut.messages.len(), whole.messages.len() - 1);
+    for (cut, whole) in cut.messages.iter().zip(&whole.messages) {
+      assert_eq!(cut.payload, whole.payload);
+      assert_eq!(cut.sender, whole.sender);
+    }
+
+    // only the header survived
+    let empty = Recording::read(&bytes[..MAGIC.len() + 4]).unwrap();
+    assert!(empty.ticks.is_empty() && empty.messages.is_empty());
+  }
+
+  #[test]
+  fn corrupt_recordings_are_refused() {
+    let mut header = MAGIC.to_vec();
+    header.extend_from_slice(&RECORDING_VERSION.to_le_bytes());
+
+    assert!(Recording::read(&b"NOTAREC!\x01\0\0\0"[..]).is_err());
+    // cut off inside the header
+    assert!(Recording::read(&header[..6]).is_err());
+
+    let mut newer = MAGIC.to_vec();
+    newer.extend_from_slice(&(RECORDING_VERSION + 1).to_le_bytes());
+    assert!(Recording::read(&newer[..]).is_err());
+
+    let mut unknown = header.clone();
+    codec::write_frame(&mut unknown, &[9]).unwrap();
+    assert!(Recording::read(&unknown[..]).is_err());
+
+    // a whole frame that's too short for what it says it is
+    let mut short = header;
+    codec::write_frame(&mut short, &[MESSAGE_FRAME, 1]).unwrap();
+    assert!(Recording::read(&short[..]).is_err());
+  }
+}
@@ -0,0 +1,404 @@
+use std::collections::HashMap;
+use std::future::{Future, IntoFuture};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant};
+
+use crate::update_manager::channel::{ChannelError, ChannelRegistry, ChannelTypeMismatch, TaskChannel};
+
+/// matches a response up with the request it answers
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct RequestId(pub u64);
+
+/// what goes over the channel between an RpcClient and an RpcServer
+pub enum RpcMessage<Req, Resp> {
+  Request { id: RequestId, body: Req },
+  Response { id: RequestId, body: Resp },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RpcError {
+  /// no response arrived before the timeout given to RpcClient::call
+  TimedOut,
+  Channel(ChannelError),
+}
+
+impl std::fmt::Display for RpcError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      RpcError::TimedOut => write!(f, "request timed out"),
+      RpcError::Channel(error) => write!(f, "{}", error),
+    }
+  }
+}
+
+impl std::error::Error for RpcError {}
+
+type RpcChannel<Req, Resp> = TaskChannel<RpcMessage<Req, Resp>>;
+type Incoming<Req, Resp> = Pin<Box<dyn Future<Output = Option<RpcMessage<Req, Resp>>> + Send>>;
+
+/// a request that hasn't been answered yet
+struct Slot<Resp> {
+  reply: Option<Resp>,
+  /// woken when the reply is routed here by someone else
+  waker: Option<Waker>,
+}
+
+struct ClientShared<Req, Resp> {
+  channel: RpcChannel<Req, Resp>,
+  next_id: AtomicU64,
+  slots: Mutex<HashMap<RequestId, Slot<Resp>>>,
+}
+
+impl<Req: Send + 'static, Resp: Send + 'static> ClientShared<Req, Resp> {
+  /// hands every response waiting on the channel to the request it belongs to
+  fn route_pending(&self) {
+    while let Some(message) = self.channel.try_recv() {
+      self.route(message);
+    }
+  }
+
+  fn route(&self, message: RpcMessage<Req, Resp>) {
+    let RpcMessage::Response { id, body } = message else {
+      // clients don't serve requests
+      return;
+    };
+
+    let Ok(mut slots) = self.slots.lock() else {
+      return;
+    };
+    // no slot means the request timed out or was dropped, so the late response is thrown away
+    if let Some(slot) = slots.get_mut(&id) {
+      slot.reply = Some(body);
+      if let Some(waker) = slot.waker.take() {
+        waker.wake();
+      }
+    }
+  }
+
+  fn take_reply(&self, id: RequestId) -> Option<Resp> {
+    let mut slots = self.slots.lock().ok()?;
+    slots.get_mut(&id)?.reply.take()
+  }
+}
+
+/// the asking side of a request/response channel.
+/// any number of requests can be in flight, each response is routed back to the request
+/// it answers by its RequestId.
+pub struct RpcClient<Req, Resp> {
+  shared: Arc<ClientShared<Req, Resp>>,
+}
+
+impl<Req, Resp> Clone for RpcClient<Req, Resp> {
+  fn clone(&self) -> Self {
+    Self {
+      shared: self.shared.clone(),
+    }
+  }
+}
+
+impl<Req: Send + 'static, Resp: Send + 'static> RpcClient<Req, Resp> {
+  pub fn new(channel: RpcChannel<Req, Resp>) -> Self {
+    Self {
+      shared: Arc::new(ClientShared {
+        channel,
+        next_id: AtomicU64::new(0),
+        slots: Mutex::new(HashMap::new()),
+      }),
+    }
+  }
+
+  /// links to the server through a typed channel, see ChannelRegistry::get_or_create_typed.
+  /// None until the server has asked for the same ID.
+  pub fn link<M: Clone + Send + 'static>(
+    registry: &ChannelRegistry<M>,
+    id: &'static str,
+  ) -> Result<Option<Self>, ChannelTypeMismatch> {
+    Ok(registry.get_or_create_typed(id)?.map(Self::new))
+  }
+
+  /// sends the request, the reply can be polled for or awaited through the PendingReply
+  pub fn call(&self, request: Req, timeout: Duration) -> Result<PendingReply<Req, Resp>, RpcError> {
+    let id = RequestId(self.shared.next_id.fetch_add(1, Ordering::Relaxed));
+    if let Ok(mut slots) = self.shared.slots.lock() {
+      slots.insert(
+        id,
+        Slot {
+          reply: None,
+          waker: None,
+        },
+      );
+    }
+
+    let pending = PendingReply {
+      id,
+      deadline: Instant::now() + timeout,
+      shared: self.shared.clone(),
+      incoming: None,
+    };
+    // dropping the PendingReply on error removes the slot again
+    self
+      .shared
+      .channel
+      .send(RpcMessage::Request { id, body: request })
+      .map_err(RpcError::Channel)?;
+    Ok(pending)
+  }
+
+  /// requests still waiting on a response
+  pub fn in_flight(&self) -> usize {
+    match self.shared.slots.lock() {
+      Ok(slots) => slots.len(),
+      Err(_) => 0,
+    }
+  }
+}
+
+/// the reply to one request. poll it every frame with try_reply, or await it.
+/// dropping it gives up on the request, a response that arrives later is thrown away.
+pub struct PendingReply<Req, Resp> {
+  id: RequestId,
+  deadline: Instant,
+  shared: Arc<ClientShared<Req, Resp>>,
+  /// waiting for whatever arrives next on the channel, only used when awaited
+  incoming: Option<Incoming<Req, Resp>>,
+}
+
+impl<Req: Send + 'static, Resp: Send + 'static> PendingReply<Req, Resp> {
+  pub fn id(&self) -> RequestId {
+    self.id
+  }
+
+  /// None while the response is still on its way
+  pub fn try_reply(&mut self) -> Option<Result<Resp, RpcError>> {
+    self.shared.route_pending();
+
+    if let Some(reply) = self.shared.take_reply(self.id) {
+      return Some(Ok(reply));
+    }
+    if Instant::now() >= self.deadline {
+      return Some(Err(RpcError::TimedOut));
+    }
+    if self.shared.channel.is_disconnected() {
+      return Some(Err(RpcError::Channel(ChannelError::Disconnected)));
+    }
+    None
+  }
+}
+
+impl<Req, Resp> Drop for PendingReply<Req, Resp> {
+  fn drop(&mut self) {
+    if let Ok(mut slots) = self.shared.slots.lock() {
+      slots.remove(&self.id);
+    }
+  }
+}
+
+/// the future behind PendingReply::into_future, without the timeout
+struct ReplyFuture<Req, Resp>(PendingReply<Req, Resp>);
+
+impl<Req: Send + 'static, Resp: Send + 'static> Future for ReplyFuture<Req, Resp> {
+  type Output = Result<Resp, RpcError>;
+
+  fn poll(self: Pin<&mut Self>, context: &mut Context<'_>) -> Poll<Self::Output> {
+    let pending = &mut self.get_mut().0;
+
+    loop {
+      pending.shared.route_pending();
+      if let Some(reply) = pending.shared.take_reply(pending.id) {
+        return Poll::Ready(Ok(reply));
+      }
+
+      // whoever routes the response to this request wakes it up
+      if let Ok(mut slots) = pending.shared.slots.lock()
+        && let Some(slot) = slots.get_mut(&pending.id)
+      {
+        slot.waker = Some(context.waker().clone());
+      }
+
+      // and if nobody else is reading the channel, this request has to
+      let incoming = pending.incoming.get_or_insert_with(|| {
+        let channel = pending.shared.channel.clone();
+        Box::pin(async move { channel.recv_async().await })
+      });
+      match incoming.as_mut().poll(context) {
+        Poll::Ready(Some(message)) => {
+          pending.incoming = None;
+          pending.shared.route(message);
+        }
+        Poll::Ready(None) => {
+          return Poll::Ready(Err(RpcError::Channel(ChannelError::Disconnected)));
+        }
+        Poll::Pending => return Poll::Pending,
+      }
+    }
+  }
+}
+
+impl<Req: Send + 'static, Resp: Send + 'static> IntoFuture for PendingReply<Req, Resp> {
+  type Output = Result<Resp, RpcError>;
+  type IntoFuture = Pin<Box<dyn Future<Output = Self::Output> + Send>>;
+
+  fn into_future(self) -> Self::IntoFuture {
+    let timeout = self.deadline.saturating_duration_since(Instant::now());
+    Box::pin(async move {
+      async_std::future::timeout(timeout, ReplyFuture(self))
+        .await
+        .unwrap_or(Err(RpcError::TimedOut))
+    })
+  }
+}
+
+/// the answering side of a request/response channel
+pub struct RpcServer<Req, Resp> {
+  channel: RpcChannel<Req, Resp>,
+}
+
+impl<Req: Send + 'static, Resp: Send + 'static> RpcServer<Req, Resp> {
+  pub fn new(channel: RpcChannel<Req, Resp>) -> Self {
+    Self { channel }
+  }
+
+  /// links to the client through a typed channel, see ChannelRegistry::get_or_create_typed.
+  /// None until the client has asked for the same ID.
+  pub fn link<M: Clone + Send + 'static>(
+    registry: &ChannelRegistry<M>,
+    id: &'static str,
+  ) -> Result<Option<Self>, ChannelTypeMismatch> {
+    Ok(registry.get_or_create_typed(id)?.map(Self::new))
+  }
+
+  /// the next request waiting to be answered, answer it with reply
+  pub fn try_next(&self) -> Option<(RequestId, Req)> {
+    while let Some(message) = self.channel.try_recv() {
+      if let RpcMessage::Request { id, body } = message {
+        return Some((id, body));
+      }
+    }
+    None
+  }
+
+  pub fn reply(&self, id: RequestId, response: Resp) -> Result<(), ChannelError> {
+    self
+      .channel
+      .send(RpcMessage::Response { id, body: response })
+  }
+
+  /// answers every waiting request, returning how many there were
+  pub fn serve(&self, mut handler: impl FnMut(Req) -> Resp) -> usize {
+    let mut served = 0;
+    while let Some((id, request)) = self.try_next() {
+      let _ = self.reply(id, handler(request));
+      served += 1;
+    }
+    served
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::update_manager::{
+    PostInit, Task, TaskRequest, TaskResult,
+    channel::{AnyChannel, TypedLink},
+    container::TaskPermission,
+    harness::Harness,
+  };
+
+  const TICK: Duration = Duration::from_millis(10);
+
+  const DOUBLE_REQUESTS: &[TaskRequest] = &[TaskRequest::LinkTypedChannel(TypedLink::of::<
+    RpcMessage<u32, u32>,
+  >("double"))];
+
+  /// answers every request with twice the number
+  struct Doubler {
+    server: Option<RpcServer<u32, u32>>,
+  }
+
+  impl Task<u32> for Doubler {
+    fn start(&mut self, _: ChannelRegistry<u32>) -> anyhow::Result<PostInit> {
+      Ok(PostInit {
+        name: "doubler",
+        tags: &[],
+        requests: DOUBLE_REQUESTS,
+      })
+    }
+
+    fn update(&mut self) -> TaskResult {
+      if let Some(server) = &self.server {
+        server.serve(|request| request * 2);
+      }
+      TaskResult::Ok
+    }
+
+    fn end(&mut self) -> anyhow::Result<()> {
+      Ok(())
+    }
+
+    fn typed_channel_linked(&mut self, _id: &'static str, channel: AnyChannel) {
+      self.server = channel.downcast().ok().map(RpcServer::new);
+    }
+  }
+
+  /// asks the doubler once, as soon as it's linked
+  struct Asker {
+    client: Option<RpcClient<u32, u32>>,
+    reply: Option<PendingReply<u32, u32>>,
+    answer: Arc<Mutex<Option<u32>>>,
+  }
+
+  impl Task<u32> for Asker {
+    fn start(&mut self, _: ChannelRegistry<u32>) -> anyhow::Result<PostInit> {
+      Ok(PostInit {
+        name: "asker",
+        tags: &[],
+        requests: DOUBLE_REQUESTS,
+      })
+    }
+
+    fn update(&mut self) -> TaskResult {
+      if let Some(client) = &self.client
+        && self.reply.is_none()
+      {
+        self.reply = client.call(21, Duration::from_secs(5)).ok();
+      }
+      if let Some(Some(Ok(answer))) = self.reply.as_mut().map(PendingReply::try_reply) {
+        *self.answer.lock().unwrap() = Some(answer);
+      }
+      TaskResult::Ok
+    }
+
+    fn end(&mut self) -> anyhow::Result<()> {
+      Ok(())
+    }
+
+    fn typed_channel_linked(&mut self, _id: &'static str, channel: AnyChannel) {
+      self.client = channel.downcast().ok().map(RpcClient::new);
+    }
+  }
+
+  #[test]
+  fn typed_links_are_handed_over() {
+    let mut harness = Harness::<u32>::new(TICK).unwrap();
+    let answer = Arc::new(Mutex::new(None));
+    harness
+      .add_task(
+        Asker {
+          client: None,
+          reply: None,
+          answer: answer.clone(),
+        },
+        TaskPermission::User,
+      )
+      .unwrap();
+    harness
+      .add_task(Doubler { server: None }, TaskPermission::User)
+      .unwrap();
+
+    harness.run(4);
+    assert_eq!(*answer.lock().unwrap(), Some(42));
+  }
+}
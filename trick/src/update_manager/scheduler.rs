@@ -0,0 +1,124 @@
+use std::panic::{self, AssertUnwindSafe};
+use std::thread::JoinHandle;
+use std::time::Instant;
+
+use crate::update_manager::{
+  TaskResult,
+  channel::{TaskChannel, TaskReceiver, TaskSender},
+  container::TaskContainer,
+  profiler::{self, Span, TaskPhase},
+  timestep::TimeStep,
+  watchdog,
+};
+
+/// run the task for a number of steps, and send back the result tagged with the index
+struct WorkerJob<M: Clone + Send + 'static> {
+  index: usize,
+  task: TaskContainer<M>,
+  steps: u32,
+  timestep: TimeStep,
+  /// the profiler's epoch, the run is timed on the worker
+  epoch: Instant,
+}
+
+/// leaves one core for the main thread, which still runs the main thread tasks.
+pub fn default_worker_count() -> usize {
+  std::thread::available_parallelism()
+    .map(|cores| cores.get().saturating_sub(1))
+    .unwrap_or(1)
+    .max(1)
+}
+
+/// a fixed set of threads that run tasks handed to them by the UpdateManager.
+/// results come back tagged with the index of the task that produced them, along with how long it took,
+/// so the manager can handle them in the same order as the single threaded loop.
+pub struct WorkerPool<M: Clone + Send + 'static> {
+  /// dropping the sender is what tells the workers to stop
+  jobs: Option<TaskSender<WorkerJob<M>>>,
+  results: TaskReceiver<(usize, TaskResult, Span)>,
+  workers: Vec<JoinHandle<()>>,
+}
+
+impl<M: Clone + Send + 'static> WorkerPool<M> {
+  pub fn new(worker_count: usize) -> anyhow::Result<Self> {
+    let (jobs, job_queue) = TaskChannel::new().split();
+    let (result_sender, results) = TaskChannel::new().split();
+
+    let mut workers = Vec::with_capacity(worker_count);
+    for worker_index in 0..worker_count.max(1) {
+      let job_queue = job_queue.clone();
+      let result_sender = result_sender.clone();
+      let worker = std::thread::Builder::new()
+        .name(format!("trick worker {worker_index}"))
+        .spawn(move || worker_loop(job_queue, result_sender))?;
+      workers.push(worker);
+    }
+
+    Ok(Self {
+      jobs: Some(jobs),
+      results,
+      workers,
+    })
+  }
+
+  pub fn worker_count(&self) -> usize {
+    self.workers.len()
+  }
+
+  /// queue a task to be run on whichever worker is free first
+  pub fn dispatch(
+    &self,
+    index: usize,
+    task: TaskContainer<M>,
+    steps: u32,
+    timestep: TimeStep,
+    epoch: Instant,
+  ) -> anyhow::Result<()> {
+    let job = WorkerJob {
+      index,
+      task,
+      steps,
+      timestep,
+      epoch,
+    };
+    match &self.jobs {
+      Some(jobs) => jobs
+        .send(job)
+        .map_err(|_| anyhow::anyhow!("worker pool has shut down")),
+      None => Err(anyhow::anyhow!("worker pool has shut down")),
+    }
+  }
+
+  /// Blocking, waits for the next task to finish
+  pub fn collect(&self) -> Option<(usize, TaskResult, Span)> {
+    self.results.recv()
+  }
+}
+
+impl<M: Clone + Send + 'static> Drop for WorkerPool<M> {
+  fn drop(&mut self) {
+    self.jobs = None;
+    for worker in self.workers.drain(..) {
+      let _ = worker.join();
+    }
+  }
+}
+
+fn worker_loop<M: Clone + Send + 'static>(
+  job_queue: TaskReceiver<WorkerJob<M>>,
+  results: TaskSender<(usize, TaskResult, Span)>,
+) {
+  while let Some(job) = job_queue.recv() {
+    // run() catches the task's own panics, this is for anything else that would take the worker
+    // down with it, leaving the manager waiting forever.
+    let (task_result, mut span) = profiler::measure(job.epoch, TaskPhase::Update, || {
+      panic::catch_unwind(AssertUnwindSafe(|| job.task.run(job.steps, job.timestep)))
+        .unwrap_or_else(|payload| TaskResult::ErrPanicked(watchdog::panic_message(&*payload)))
+    });
+    span.task = job.task.get_label();
+
+    if results.send((job.index, task_result, span)).is_err() {
+      break;
+    }
+  }
+}
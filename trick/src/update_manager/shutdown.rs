@@ -0,0 +1,107 @@
+use std::time::Duration;
+
+use crate::update_manager::{
+  TaskTag,
+  channel::TaskChannel,
+  container::{TaskContainer, TaskId},
+};
+
+/// how long a task's end() gets before the manager moves on without it
+pub const DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ShutdownOutcome {
+  Ended,
+  /// end() was already called when the task was stopped
+  AlreadyStopped,
+  Failed(String),
+  /// end() was still running when the timeout ran out, the task is left behind on its thread
+  TimedOut,
+}
+
+#[derive(Clone, Debug)]
+pub struct TaskShutdown {
+  pub id: TaskId,
+  pub label: &'static str,
+  pub outcome: ShutdownOutcome,
+}
+
+/// every task in the order it was ended
+#[derive(Clone, Debug, Default)]
+pub struct ShutdownReport {
+  pub tasks: Vec<TaskShutdown>,
+}
+
+impl ShutdownReport {
+  /// tasks that failed or timed out
+  pub fn failures(&self) -> impl Iterator<Item = &TaskShutdown> {
+    self.tasks.iter().filter(|task| {
+      matches!(
+        task.outcome,
+        ShutdownOutcome::Failed(_) | ShutdownOutcome::TimedOut
+      )
+    })
+  }
+
+  pub fn is_clean(&self) -> bool {
+    self.failures().next().is_none()
+  }
+}
+
+/// reverse update order, so tasks end before whatever they ran after.
+/// DropLast tasks (like the window) go after everything else, still in reverse order.
+pub fn shutdown_order<M: Clone + Send + 'static>(
+  tasks: &[TaskContainer<M>],
+  stages: &[Vec<usize>],
+) -> Vec<usize> {
+  let reversed: Vec<usize> = stages.iter().flatten().rev().copied().collect();
+  let is_drop_last = |index: &usize| tasks[*index].get_tag().contains(&TaskTag::DropLast);
+
+  let mut order: Vec<usize> = reversed
+    .iter()
+    .copied()
+    .filter(|index| !is_drop_last(index))
+    .collect();
+  order.extend(reversed.iter().copied().filter(is_drop_last));
+  order
+}
+
+/// calls end() on the task.
+/// main thread tasks are ended right here, since they can't be moved to another thread,
+/// everything else is ended on its own thread so a hung end() can be given up on.
+pub fn end_task<M: Clone + Send + 'static>(
+  task: &TaskContainer<M>,
+  timeout: Duration,
+) -> ShutdownOutcome {
+  if task.is_stopped() {
+    return ShutdownOutcome::AlreadyStopped;
+  }
+
+  if task.get_tag().contains(&TaskTag::MainThread) {
+    return match task.end_task() {
+      Ok(()) => ShutdownOutcome::Ended,
+      Err(error) => ShutdownOutcome::Failed(format!("{:#}", error)),
+    };
+  }
+
+  let (sender, receiver) = TaskChannel::new().split();
+  let ending = task.clone();
+  let spawned = std::thread::Builder::new()
+    .name(format!("trick shutdown {}", task.get_label()))
+    .spawn(move || {
+      let _ = sender.send(ending.end_task().map_err(|error| format!("{:#}", error)));
+    });
+  if let Err(error) = spawned {
+    return ShutdownOutcome::Failed(format!("failed to spawn shutdown thread: {}", error));
+  }
+
+  match receiver.recv_timeout(timeout) {
+    Some(Ok(())) => ShutdownOutcome::Ended,
+    Some(Err(error)) => ShutdownOutcome::Failed(error),
+    // the thread dropped its sender without sending anything
+    None if receiver.is_disconnected() => {
+      ShutdownOutcome::Failed(String::from("task panicked while ending"))
+    }
+    None => ShutdownOutcome::TimedOut,
+  }
+}
@@ -0,0 +1,134 @@
+use crate::update_manager::Task;
+
+/// lets a task keep its state through a reload (TaskResult::ErrReload, a restart after a failure,
+/// or a hot reload of its library). the task hands it out through Task::snapshot.
+///
+/// the state is saved right before end(), and restored right after start().
+pub trait Snapshot {
+  /// bumped whenever the saved format changes,
+  /// restore is given the version the state was saved with so old state can be upgraded.
+  fn version(&self) -> u32;
+  fn save(&self) -> anyhow::Result<Vec<u8>>;
+  fn restore(&mut self, version: u32, state: &[u8]) -> anyhow::Result<()>;
+}
+
+#[derive(Clone, Debug)]
+pub struct SavedState {
+  pub version: u32,
+  pub bytes: Vec<u8>,
+}
+
+/// None for tasks without a snapshot
+pub fn save<M: Clone + Send + 'static>(
+  task: &mut dyn Task<M>,
+) -> anyhow::Result<Option<SavedState>> {
+  let Some(snapshot) = task.snapshot() else {
+    return Ok(None);
+  };
+
+  Ok(Some(SavedState {
+    version: snapshot.version(),
+    bytes: snapshot.save()?,
+  }))
+}
+
+pub fn restore<M: Clone + Send + 'static>(
+  task: &mut dyn Task<M>,
+  saved: &SavedState,
+) -> anyhow::Result<()> {
+  match task.snapshot() {
+    Some(snapshot) => snapshot.restore(saved.version, &saved.bytes),
+    // the new code of a hot reloaded task might not have a snapshot anymore
+    None => Ok(()),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::{Arc, Mutex};
+  use std::time::Duration;
+
+  use super::*;
+  use crate::update_manager::{
+    PostInit, TaskResult, TaskTag, channel::ChannelRegistry, container::TaskPermission,
+    harness::Harness, supervisor::RestartPolicy,
+  };
+
+  const TICK: Duration = Duration::from_millis(10);
+
+  const COUNTER_TAGS: &[TaskTag] = &[TaskTag::Restart(RestartPolicy {
+    backoff: Duration::from_millis(10),
+    ..RestartPolicy::DEFAULT
+  })];
+
+  /// asks for a reload on its third update, and keeps counting through it
+  struct Counter {
+    count: u32,
+    starts: u32,
+    seen: Arc<Mutex<Vec<(u32, u32)>>>,
+  }
+
+  impl Snapshot for Counter {
+    fn version(&self) -> u32 {
+      1
+    }
+
+    fn save(&self) -> anyhow::Result<Vec<u8>> {
+      Ok(self.count.to_le_bytes().to_vec())
+    }
+
+    fn restore(&mut self, _version: u32, state: &[u8]) -> anyhow::Result<()> {
+      self.count = u32::from_le_bytes(state.try_into()?);
+      Ok(())
+    }
+  }
+
+  impl Task<u32> for Counter {
+    fn start(&mut self, _: ChannelRegistry<u32>) -> anyhow::Result<PostInit> {
+      self.count = 0;
+      self.starts += 1;
+      Ok(PostInit {
+        name: "counter",
+        tags: COUNTER_TAGS,
+        requests: &[],
+      })
+    }
+
+    fn update(&mut self) -> TaskResult {
+      self.count += 1;
+      self.seen.lock().unwrap().push((self.starts, self.count));
+      if self.starts == 1 && self.count == 3 {
+        return TaskResult::ErrReload;
+      }
+      TaskResult::Ok
+    }
+
+    fn end(&mut self) -> anyhow::Result<()> {
+      Ok(())
+    }
+
+    fn snapshot(&mut self) -> Option<&mut dyn Snapshot> {
+      Some(self)
+    }
+  }
+
+  #[test]
+  fn reload_keeps_the_snapshot() {
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let mut harness = Harness::<u32>::new(TICK).unwrap();
+    let counter = Counter {
+      count: 0,
+      starts: 0,
+      seen: seen.clone(),
+    };
+    harness.add_task(counter, TaskPermission::User).unwrap();
+    harness.run(6);
+
+    assert_eq!(harness.results_for("counter")[2].1, TaskResult::ErrReload);
+    // started again after the reload, but carried on from where it was
+    assert_eq!(
+      seen.lock().unwrap()[..5],
+      [(1, 1), (1, 2), (1, 3), (2, 4), (2, 5)]
+    );
+  }
+}
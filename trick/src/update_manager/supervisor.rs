@@ -0,0 +1,261 @@
+use std::time::Duration;
+
+/// what the manager does with a task once it has used up its restarts
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Escalation {
+  /// stop updating the task, the rest of the program keeps running.
+  Disable,
+  /// hand the failure up to whoever is running the UpdateManager,
+  /// which ends the update loop with UpdateReturn::Shutdown.
+  Escalate,
+}
+
+/// how a task is restarted after it returns ErrReload or ErrFatal.
+/// declared by the task through TaskTag::Restart, otherwise RestartPolicy::DEFAULT is used.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RestartPolicy {
+  pub max_retries: u32,
+  /// wait before the first restart, doubled after every restart that didn't stick.
+  pub backoff: Duration,
+  pub max_backoff: Duration,
+  /// once the task has been running this long without failing, its retries are forgiven.
+  pub reset_after: Duration,
+  /// what happens once the retries run out
+  pub exhausted: Escalation,
+}
+
+impl RestartPolicy {
+  pub const DEFAULT: Self = Self {
+    max_retries: 3,
+    backoff: Duration::from_millis(100),
+    max_backoff: Duration::from_secs(5),
+    reset_after: Duration::from_secs(10),
+    exhausted: Escalation::Disable,
+  };
+
+  /// never restart, the task is disabled on its first failure
+  pub const DISABLE: Self = Self {
+    max_retries: 0,
+    exhausted: Escalation::Disable,
+    ..Self::DEFAULT
+  };
+
+  /// never restart, the first failure is escalated (the old behaviour for failed reloads)
+  pub const ESCALATE: Self = Self {
+    max_retries: 0,
+    exhausted: Escalation::Escalate,
+    ..Self::DEFAULT
+  };
+
+  pub fn backoff_for(&self, attempt: u32) -> Duration {
+    let multiplier = 1u32.checked_shl(attempt).unwrap_or(u32::MAX);
+    self
+      .backoff
+      .saturating_mul(multiplier)
+      .min(self.max_backoff)
+  }
+}
+
+impl Default for RestartPolicy {
+  fn default() -> Self {
+    Self::DEFAULT
+  }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum RestartAction {
+  /// the task failed, and will be restarted once the backoff is over
+  Scheduled {
+    backoff: Duration,
+  },
+  Restarted,
+  Disabled,
+  Escalated,
+}
+
+/// one entry in the manager's restart log, see UpdateManager::restart_log
+#[derive(Clone, Debug)]
+pub struct RestartRecord {
+  pub task: &'static str,
+  /// which restart this was, starting at 1. 0 for tasks that were never restarted.
+  pub attempt: u32,
+  pub reason: String,
+  pub action: RestartAction,
+  /// time since the manager started updating
+  pub at: Duration,
+}
+
+pub enum Verdict {
+  Restart { backoff: Duration },
+  Disable,
+  Escalate,
+}
+
+/// the restart bookkeeping for a single task
+#[derive(Clone)]
+pub struct SupervisorState {
+  policy: RestartPolicy,
+  retries: u32,
+  restart_at: Option<Duration>,
+  last_restart: Duration,
+  disabled: bool,
+}
+
+impl SupervisorState {
+  pub fn new(policy: RestartPolicy) -> Self {
+    Self {
+      policy,
+      retries: 0,
+      restart_at: None,
+      last_restart: Duration::ZERO,
+      disabled: false,
+    }
+  }
+
+  pub fn retries(&self) -> u32 {
+    self.retries
+  }
+
+  pub fn is_disabled(&self) -> bool {
+    self.disabled
+  }
+
+  /// disabled tasks, and tasks waiting on a restart aren't updated
+  pub fn is_suspended(&self) -> bool {
+    self.disabled || self.restart_at.is_some()
+  }
+
+  pub fn restart_due(&self, now: Duration) -> bool {
+    matches!(self.restart_at, Some(restart_at) if restart_at <= now)
+  }
+
+  /// note a failure, and decide what to do about it
+  pub fn fail(&mut self, now: Duration) -> Verdict {
+    self.restart_at = None;
+
+    if self.retries >= self.policy.max_retries {
+      self.disabled = true;
+      return match self.policy.exhausted {
+        Escalation::Disable => Verdict::Disable,
+        Escalation::Escalate => Verdict::Escalate,
+      };
+    }
+
+    let backoff = self.policy.backoff_for(self.retries);
+    self.retries += 1;
+    self.restart_at = Some(now + backoff);
+
+    Verdict::Restart { backoff }
+  }
+
+  pub fn restarted(&mut self, now: Duration) {
+    self.restart_at = None;
+    self.last_restart = now;
+  }
+
+  pub fn succeeded(&mut self, now: Duration) {
+    if self.retries > 0 && now.saturating_sub(self.last_restart) >= self.policy.reset_after {
+      self.retries = 0;
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::update_manager::{
+    PostInit, Task, TaskResult, TaskTag, UpdateReturn, channel::ChannelRegistry,
+    container::TaskPermission, control::TaskState, harness::Harness,
+  };
+
+  const TICK: Duration = Duration::from_millis(10);
+
+  const FLAKY_TAGS: &[TaskTag] = &[TaskTag::Restart(RestartPolicy {
+    max_retries: 1,
+    backoff: Duration::from_millis(20),
+    ..RestartPolicy::DEFAULT
+  })];
+  const FRAGILE_TAGS: &[TaskTag] = &[TaskTag::Restart(RestartPolicy {
+    max_retries: 0,
+    exhausted: Escalation::Escalate,
+    ..RestartPolicy::DEFAULT
+  })];
+
+  /// fails every update
+  struct Broken {
+    name: &'static str,
+    tags: &'static [TaskTag],
+  }
+
+  impl Task<u32> for Broken {
+    fn start(&mut self, _: ChannelRegistry<u32>) -> anyhow::Result<PostInit> {
+      Ok(PostInit {
+        name: self.name,
+        tags: self.tags,
+        requests: &[],
+      })
+    }
+
+    fn update(&mut self) -> TaskResult {
+      TaskResult::ErrFatal("broken".into())
+    }
+
+    fn end(&mut self) -> anyhow::Result<()> {
+      Ok(())
+    }
+  }
+
+  #[test]
+  fn restart_policy_restarts_then_disables() {
+    let mut harness = Harness::<u32>::new(TICK).unwrap();
+    let flaky = Broken {
+      name: "flaky",
+      tags: FLAKY_TAGS,
+    };
+    harness.add_task(flaky, TaskPermission::User).unwrap();
+
+    let reports = harness.run(10);
+    assert!(
+      reports
+        .iter()
+        .all(|report| report.update_return == UpdateReturn::Ok)
+    );
+    // updated once, restarted after the backoff, and failed again
+    assert_eq!(harness.results_for("flaky").len(), 2);
+    assert_eq!(
+      harness.results_for("flaky")[0].1,
+      TaskResult::ErrFatal("broken".into())
+    );
+
+    let actions: Vec<RestartAction> = harness
+      .manager()
+      .restart_log()
+      .map(|record| record.action.clone())
+      .collect();
+    assert_eq!(
+      actions,
+      [
+        RestartAction::Scheduled {
+          backoff: Duration::from_millis(20)
+        },
+        RestartAction::Restarted,
+        RestartAction::Disabled,
+      ]
+    );
+    assert_eq!(harness.manager().list_tasks()[0].state, TaskState::Disabled);
+  }
+
+  #[test]
+  fn escalated_failure_shuts_down() {
+    let mut harness = Harness::<u32>::new(TICK).unwrap();
+    let fragile = Broken {
+      name: "fragile",
+      tags: FRAGILE_TAGS,
+    };
+    harness.add_task(fragile, TaskPermission::User).unwrap();
+
+    let reports = harness.run(10);
+    assert_eq!(reports.len(), 1);
+    assert_eq!(reports[0].update_return, UpdateReturn::Shutdown);
+  }
+}
@@ -0,0 +1,360 @@
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+/// one frame's worth of time, as handed out by TimeService::advance
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FrameTime {
+  /// the frame time after the time scale, zero while paused unless the frame was stepped
+  pub delta: Duration,
+  /// the frame time as measured (or given to update_tasks_with_delta)
+  pub unscaled_delta: Duration,
+  /// scaled time before this frame, the sum of every earlier scaled delta
+  pub elapsed: Duration,
+  pub unscaled_elapsed: Duration,
+  pub time_scale: f32,
+  pub paused: bool,
+}
+
+struct TimeState {
+  time_scale: f32,
+  paused: bool,
+  /// frames queued by TimeService::step, only used up while paused
+  pending_steps: u32,
+  /// the last frame handed out
+  frame: FrameTime,
+  /// after the last frame, what the next one's elapsed times start from
+  elapsed: Duration,
+  unscaled_elapsed: Duration,
+}
+
+/// game time, shared between the UpdateManager and whoever wants to read or control it.
+/// the manager advances it once per frame, and every task's TimeStep::delta is the scaled delta.
+/// get it from UpdateManager::time. tasks read it with ManagerHandle::time,
+/// and control it through the ManagerHandle, which needs a permission that can manage tasks.
+#[derive(Clone)]
+pub struct TimeService {
+  state: Arc<Mutex<TimeState>>,
+}
+
+impl Default for TimeService {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl TimeService {
+  pub fn new() -> Self {
+    Self {
+      state: Arc::new(Mutex::new(TimeState {
+        time_scale: 1.0,
+        paused: false,
+        pending_steps: 0,
+        frame: FrameTime {
+          time_scale: 1.0,
+          ..FrameTime::default()
+        },
+        elapsed: Duration::ZERO,
+        unscaled_elapsed: Duration::ZERO,
+      })),
+    }
+  }
+
+  fn lock(&self) -> MutexGuard<'_, TimeState> {
+    // nothing in here can panic halfway through an update
+    self
+      .state
+      .lock()
+      .unwrap_or_else(|poisoned| poisoned.into_inner())
+  }
+
+  /// the last frame's time
+  pub fn frame(&self) -> FrameTime {
+    self.lock().frame
+  }
+
+  pub fn delta(&self) -> Duration {
+    self.lock().frame.delta
+  }
+
+  pub fn unscaled_delta(&self) -> Duration {
+    self.lock().frame.unscaled_delta
+  }
+
+  /// scaled time up to and including the last frame
+  pub fn elapsed(&self) -> Duration {
+    self.lock().elapsed
+  }
+
+  pub fn unscaled_elapsed(&self) -> Duration {
+    self.lock().unscaled_elapsed
+  }
+
+  pub fn time_scale(&self) -> f32 {
+    self.lock().time_scale
+  }
+
+  /// 1 is real time, 0.5 is half speed. negative and non finite scales are ignored.
+  /// takes effect from the next frame.
+  pub fn set_time_scale(&self, time_scale: f32) {
+    if !time_scale.is_finite() || time_scale < 0.0 {
+      return;
+    }
+    self.lock().time_scale = time_scale;
+  }
+
+  pub fn is_paused(&self) -> bool {
+    self.lock().paused
+  }
+
+  /// stops scaled time, tasks keep updating but with a zero delta.
+  /// fixed rate tasks don't update at all, since their clock doesn't move.
+  pub fn pause(&self) {
+    self.lock().paused = true;
+  }
+
+  /// drops any steps that were queued but not run
+  pub fn resume(&self) {
+    let mut state = self.lock();
+    state.paused = false;
+    state.pending_steps = 0;
+  }
+
+  /// returns whether it's paused now
+  pub fn toggle_pause(&self) -> bool {
+    let paused = !self.is_paused();
+    if paused {
+      self.pause();
+    } else {
+      self.resume();
+    }
+    paused
+  }
+
+  /// while paused, runs the next frame as if it wasn't. calling it again queues another frame.
+  /// does nothing while running.
+  pub fn step(&self) {
+    let mut state = self.lock();
+    if state.paused {
+      state.pending_steps += 1;
+    }
+  }
+
+  /// read only access, for handing out to whoever shouldn't control time
+  pub fn reader(&self) -> TimeReader {
+    TimeReader { time: self.clone() }
+  }
+
+  /// called by the UpdateManager at the start of every frame
+  pub fn advance(&self, unscaled_delta: Duration) -> FrameTime {
+    let mut state = self.lock();
+
+    let stepping = state.paused && state.pending_steps > 0;
+    if stepping {
+      state.pending_steps -= 1;
+    }
+    let delta = if state.paused && !stepping {
+      Duration::ZERO
+    } else {
+      unscaled_delta.mul_f32(state.time_scale)
+    };
+
+    let frame = FrameTime {
+      delta,
+      unscaled_delta,
+      elapsed: state.elapsed,
+      unscaled_elapsed: state.unscaled_elapsed,
+      time_scale: state.time_scale,
+      paused: state.paused && !stepping,
+    };
+    state.elapsed += delta;
+    state.unscaled_elapsed += unscaled_delta;
+    state.frame = frame;
+    frame
+  }
+}
+
+/// a TimeService that can only be read, see ManagerHandle::time
+#[derive(Clone)]
+pub struct TimeReader {
+  time: TimeService,
+}
+
+impl TimeReader {
+  /// the last frame's time
+  pub fn frame(&self) -> FrameTime {
+    self.time.frame()
+  }
+
+  pub fn delta(&self) -> Duration {
+    self.time.delta()
+  }
+
+  pub fn unscaled_delta(&self) -> Duration {
+    self.time.unscaled_delta()
+  }
+
+  /// scaled time up to and including the last frame
+  pub fn elapsed(&self) -> Duration {
+    self.time.elapsed()
+  }
+
+  pub fn unscaled_elapsed(&self) -> Duration {
+    self.time.unscaled_elapsed()
+  }
+
+  pub fn time_scale(&self) -> f32 {
+    self.time.time_scale()
+  }
+
+  pub fn is_paused(&self) -> bool {
+    self.time.is_paused()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::{Arc, Mutex};
+
+  use super::*;
+  use crate::update_manager::{
+    PostInit, Task, TaskRequest, TaskResult,
+    channel::ChannelRegistry,
+    container::{DeniedAction, TaskPermission},
+    control::ManagerHandle,
+    harness::Harness,
+  };
+
+  const FRAME: Duration = Duration::from_millis(20);
+
+  #[test]
+  fn time_scale_only_changes_the_scaled_delta() {
+    let time = TimeService::new();
+    time.advance(FRAME);
+    time.set_time_scale(0.5);
+    let frame = time.advance(FRAME);
+
+    assert_eq!(frame.delta, FRAME / 2);
+    assert_eq!(frame.unscaled_delta, FRAME);
+    assert_eq!(frame.time_scale, 0.5);
+    // elapsed is from before the frame, the service's own is after it
+    assert_eq!(frame.elapsed, FRAME);
+    assert_eq!(time.elapsed(), FRAME + FRAME / 2);
+    assert_eq!(time.unscaled_elapsed(), FRAME * 2);
+
+    time.set_time_scale(-1.0);
+    time.set_time_scale(f32::NAN);
+    assert_eq!(time.time_scale(), 0.5);
+  }
+
+  #[test]
+  fn pausing_stops_scaled_time() {
+    let time = TimeService::new();
+    time.pause();
+    let frame = time.advance(FRAME);
+    assert!(frame.paused);
+    assert_eq!(frame.delta, Duration::ZERO);
+    assert_eq!(frame.unscaled_delta, FRAME);
+    assert_eq!(time.elapsed(), Duration::ZERO);
+
+    assert!(!time.toggle_pause());
+    let frame = time.advance(FRAME);
+    assert!(!frame.paused);
+    assert_eq!(frame.delta, FRAME);
+    assert_eq!(time.unscaled_elapsed(), FRAME * 2);
+  }
+
+  #[test]
+  fn stepping_runs_single_frames_while_paused() {
+    let time = TimeService::new();
+    time.pause();
+    time.step();
+    time.step();
+
+    for _ in 0..2 {
+      let frame = time.advance(FRAME);
+      assert!(!frame.paused);
+      assert_eq!(frame.delta, FRAME);
+    }
+    assert_eq!(time.advance(FRAME).delta, Duration::ZERO);
+    assert!(time.is_paused());
+
+    // steps are only queued while paused, and resuming drops the ones left over
+    time.step();
+    time.resume();
+    time.step();
+    time.pause();
+    assert_eq!(time.advance(FRAME).delta, Duration::ZERO);
+  }
+
+  /// pauses the game on its first update, and writes down whether it saw time paused
+  struct Pauser {
+    manager: Option<ManagerHandle<u32>>,
+    paused: Arc<Mutex<Vec<bool>>>,
+  }
+
+  impl Task<u32> for Pauser {
+    fn start(&mut self, _: ChannelRegistry<u32>) -> anyhow::Result<PostInit> {
+      Ok(PostInit {
+        name: "pauser",
+        tags: &[],
+        requests: &[TaskRequest::ManagerControl],
+      })
+    }
+
+    fn manager_linked(&mut self, manager: ManagerHandle<u32>) {
+      self.manager = Some(manager);
+    }
+
+    fn update(&mut self) -> TaskResult {
+      let Some(manager) = &self.manager else {
+        return TaskResult::Ok;
+      };
+      let mut paused = self.paused.lock().unwrap();
+      if paused.is_empty() {
+        manager.pause().unwrap();
+      }
+      paused.push(manager.time().is_paused());
+      TaskResult::Ok
+    }
+
+    fn end(&mut self) -> anyhow::Result<()> {
+      Ok(())
+    }
+  }
+
+  fn pause_from_a_task(permission: TaskPermission) -> (Harness<u32>, Vec<bool>) {
+    let paused = Arc::new(Mutex::new(Vec::new()));
+    let mut harness = Harness::<u32>::new(FRAME).unwrap();
+    let pauser = Pauser {
+      manager: None,
+      paused: paused.clone(),
+    };
+    harness.add_task(pauser, permission).unwrap();
+    harness.run(2);
+    let paused = paused.lock().unwrap().clone();
+    (harness, paused)
+  }
+
+  #[test]
+  fn tasks_that_manage_tasks_can_control_time() {
+    let (harness, paused) = pause_from_a_task(TaskPermission::Root);
+    assert_eq!(paused, [false, true]);
+    assert!(harness.manager().time().is_paused());
+    assert_eq!(harness.manager().denied_actions().count(), 0);
+    harness.finish();
+  }
+
+  #[test]
+  fn other_tasks_can_only_read_time() {
+    let (harness, paused) = pause_from_a_task(TaskPermission::User);
+    assert_eq!(paused, [false, false]);
+    assert!(!harness.manager().time().is_paused());
+    let denied: Vec<&DeniedAction> = harness
+      .manager()
+      .denied_actions()
+      .map(|denied| &denied.action)
+      .collect();
+    assert_eq!(denied, [&DeniedAction::ControlTime]);
+    harness.finish();
+  }
+}
@@ -0,0 +1,156 @@
+use std::time::Duration;
+
+/// if a frame takes so long that a fixed rate task falls further behind than this,
+/// the leftover time is thrown away instead of trying to catch up (spiral of death).
+pub const MAX_CATCH_UP_STEPS: u32 = 8;
+
+/// handed to Task::timestep right before every call to Task::update
+#[derive(Clone, Copy, Debug)]
+pub struct TimeStep {
+  /// time since this task was last updated, always the fixed step for fixed rate tasks.
+  /// scaled by the time::TimeService, and zero while it's paused.
+  pub delta: Duration,
+  /// the frame's time before the time scale and pausing, for things that run on real time (eg: ui)
+  pub unscaled_delta: Duration,
+  /// how far the simulation is between its last fixed step and the next one, from 0 to 1.
+  /// variable rate tasks (like the renderer) can use this to interpolate between fixed states.
+  /// always 0 for fixed rate tasks.
+  pub alpha: f32,
+  /// how many frames the UpdateManager had updated before this one, shared by every step in a frame
+  pub frame: u64,
+  /// scaled time at the start of the frame, see time::TimeService::elapsed
+  pub elapsed: Duration,
+  pub paused: bool,
+}
+
+impl Default for TimeStep {
+  fn default() -> Self {
+    Self {
+      delta: Duration::ZERO,
+      unscaled_delta: Duration::ZERO,
+      alpha: 0.0,
+      frame: 0,
+      elapsed: Duration::ZERO,
+      paused: false,
+    }
+  }
+}
+
+/// accumulates frame time for a fixed rate task, and hands it out in whole steps.
+#[derive(Clone)]
+pub struct FixedClock {
+  step: Duration,
+  accumulator: Duration,
+}
+
+impl FixedClock {
+  pub fn new(rate_hz: u32) -> Self {
+    Self {
+      step: Duration::from_secs(1) / rate_hz.max(1),
+      accumulator: Duration::ZERO,
+    }
+  }
+
+  pub fn step(&self) -> Duration {
+    self.step
+  }
+
+  /// add the frame time, and return how many steps need to run to catch up.
+  pub fn advance(&mut self, delta: Duration) -> u32 {
+    self.accumulator += delta;
+
+    let mut steps = 0;
+    while self.accumulator >= self.step {
+      self.accumulator -= self.step;
+      steps += 1;
+
+      if steps == MAX_CATCH_UP_STEPS {
+        self.accumulator = Duration::ZERO;
+        break;
+      }
+    }
+
+    steps
+  }
+
+  pub fn alpha(&self) -> f32 {
+    self.accumulator.as_secs_f32() / self.step.as_secs_f32()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::{Arc, Mutex};
+
+  use super::*;
+  use crate::update_manager::{
+    PostInit, Task, TaskResult, TaskTag, channel::ChannelRegistry, container::TaskPermission,
+    harness::Harness,
+  };
+
+  const FIXED_TAGS: &[TaskTag] = &[TaskTag::FixedRate(100)];
+
+  struct Stepper {
+    steps: Arc<Mutex<u32>>,
+  }
+
+  impl Task<u32> for Stepper {
+    fn start(&mut self, _: ChannelRegistry<u32>) -> anyhow::Result<PostInit> {
+      Ok(PostInit {
+        name: "stepper",
+        tags: FIXED_TAGS,
+        requests: &[],
+      })
+    }
+
+    fn timestep(&mut self, timestep: &TimeStep) {
+      assert_eq!(timestep.delta, Duration::from_millis(10));
+    }
+
+    fn update(&mut self) -> TaskResult {
+      *self.steps.lock().unwrap() += 1;
+      TaskResult::Ok
+    }
+
+    fn end(&mut self) -> anyhow::Result<()> {
+      Ok(())
+    }
+  }
+
+  #[test]
+  fn fixed_rate_catches_up_on_long_ticks() {
+    let steps = Arc::new(Mutex::new(0));
+    let mut harness = Harness::<u32>::new(Duration::from_millis(35)).unwrap();
+    harness
+      .add_task(
+        Stepper {
+          steps: steps.clone(),
+        },
+        TaskPermission::User,
+      )
+      .unwrap();
+    let steps_per_tick = || {
+      let mut steps = steps.lock().unwrap();
+      std::mem::take(&mut *steps)
+    };
+
+    // 35ms is 3 steps with 5ms left over, which makes the next 40ms 4 steps
+    harness.tick();
+    assert_eq!(steps_per_tick(), 3);
+    harness.tick();
+    assert_eq!(steps_per_tick(), 4);
+
+    // too short for a step, so it doesn't update at all
+    harness.set_tick_length(Duration::from_millis(5));
+    assert_eq!(harness.tick().result("stepper"), None);
+    assert_eq!(steps_per_tick(), 0);
+
+    // a stall is only caught up on so far, the rest is thrown away
+    harness.set_tick_length(Duration::from_secs(1));
+    harness.tick();
+    assert_eq!(steps_per_tick(), MAX_CATCH_UP_STEPS);
+    harness.set_tick_length(Duration::from_millis(5));
+    harness.tick();
+    assert_eq!(steps_per_tick(), 0);
+  }
+}
@@ -0,0 +1,198 @@
+use std::any::Any;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
+
+use crate::update_manager::{
+  channel::{TaskChannel, TaskReceiver, TaskSender},
+  container::TaskId,
+  profiler::{self, TaskPhase},
+};
+
+/// how long a task can spend in one call before the watchdog reports it,
+/// unless it has a TaskTag::TimeBudget of its own
+pub const DEFAULT_TIME_BUDGET: Duration = Duration::from_secs(2);
+/// how often the watchdog thread checks on running tasks
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// a task that ran past its time budget. reported once per call, while it's still running,
+/// so it's known which task froze the loop even if it never comes back.
+#[derive(Clone, Debug)]
+pub struct HangReport {
+  pub task: TaskId,
+  pub label: &'static str,
+  pub phase: TaskPhase,
+  /// the thread it's stuck on
+  pub thread: Arc<str>,
+  /// how long it had been running when it was caught
+  pub running_for: Duration,
+  pub budget: Duration,
+}
+
+impl std::fmt::Display for HangReport {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(
+      f,
+      "task {} has been in {} for {:?} on {}, its budget is {:?}",
+      self.label,
+      self.phase.name(),
+      self.running_for,
+      self.thread,
+      self.budget
+    )
+  }
+}
+
+/// one call into a task that hasn't returned yet
+struct Watched {
+  task: TaskId,
+  label: &'static str,
+  phase: TaskPhase,
+  thread: Arc<str>,
+  started: Instant,
+  budget: Duration,
+  reported: bool,
+}
+
+struct WatchdogShared {
+  running: Mutex<HashMap<u64, Watched>>,
+  next_watch: AtomicU64,
+  /// nanoseconds, see Watchdog::set_default_budget
+  default_budget: AtomicU64,
+  reports: TaskSender<HangReport>,
+}
+
+/// keeps an eye on every call into a task from a thread of its own,
+/// since a task stuck in a loop never gives control back to whoever would notice.
+/// the UpdateManager owns one, see UpdateManager::hang_log.
+#[derive(Clone)]
+pub struct Watchdog {
+  shared: Arc<WatchdogShared>,
+}
+
+impl Watchdog {
+  /// the watchdog thread stops once every clone of the watchdog has been dropped
+  pub fn new() -> anyhow::Result<(Self, TaskReceiver<HangReport>)> {
+    let (reports, report_receiver) = TaskChannel::new().split();
+    let shared = Arc::new(WatchdogShared {
+      running: Mutex::new(HashMap::new()),
+      next_watch: AtomicU64::new(0),
+      default_budget: AtomicU64::new(DEFAULT_TIME_BUDGET.as_nanos() as u64),
+      reports,
+    });
+
+    let weak = Arc::downgrade(&shared);
+    std::thread::Builder::new()
+      .name(String::from("trick watchdog"))
+      .spawn(move || watchdog_loop(weak))?;
+
+    Ok((Self { shared }, report_receiver))
+  }
+
+  pub fn default_budget(&self) -> Duration {
+    Duration::from_nanos(self.shared.default_budget.load(Ordering::Relaxed))
+  }
+
+  pub fn set_default_budget(&self, budget: Duration) {
+    self
+      .shared
+      .default_budget
+      .store(budget.as_nanos() as u64, Ordering::Relaxed);
+  }
+
+  /// watches the call until the guard is dropped, None uses the default budget
+  pub fn watch(
+    &self,
+    task: TaskId,
+    label: &'static str,
+    phase: TaskPhase,
+    budget: Option<Duration>,
+  ) -> WatchGuard {
+    let id = self.shared.next_watch.fetch_add(1, Ordering::Relaxed);
+    let watched = Watched {
+      task,
+      label,
+      phase,
+      thread: profiler::current_thread_name(),
+      started: Instant::now(),
+      budget: budget.unwrap_or_else(|| self.default_budget()),
+      reported: false,
+    };
+    if let Ok(mut running) = self.shared.running.lock() {
+      running.insert(id, watched);
+    }
+
+    WatchGuard {
+      shared: self.shared.clone(),
+      id,
+    }
+  }
+}
+
+/// stops watching the call once dropped, see Watchdog::watch
+pub struct WatchGuard {
+  shared: Arc<WatchdogShared>,
+  id: u64,
+}
+
+impl Drop for WatchGuard {
+  fn drop(&mut self) {
+    let Ok(mut running) = self.shared.running.lock() else {
+      return;
+    };
+    if let Some(watched) = running.remove(&self.id)
+      && watched.reported
+    {
+      logger::info!(
+        "task {} came back from {} after {:?}",
+        watched.label,
+        watched.phase.name(),
+        watched.started.elapsed()
+      );
+    }
+  }
+}
+
+fn watchdog_loop(shared: Weak<WatchdogShared>) {
+  loop {
+    std::thread::sleep(POLL_INTERVAL);
+    let Some(shared) = shared.upgrade() else {
+      return;
+    };
+    let Ok(mut running) = shared.running.lock() else {
+      return;
+    };
+
+    for watched in running.values_mut() {
+      let running_for = watched.started.elapsed();
+      if watched.reported || running_for < watched.budget {
+        continue;
+      }
+      watched.reported = true;
+
+      let report = HangReport {
+        task: watched.task,
+        label: watched.label,
+        phase: watched.phase,
+        thread: watched.thread.clone(),
+        running_for,
+        budget: watched.budget,
+      };
+      // printed from here, the thread that would print it otherwise might be the one that's stuck
+      logger::warn!("{}", report);
+      let _ = shared.reports.send(report);
+    }
+  }
+}
+
+/// the message a panic was started with, for panic::catch_unwind results
+pub fn panic_message(payload: &(dyn Any + Send)) -> String {
+  if let Some(message) = payload.downcast_ref::<&str>() {
+    return message.to_string();
+  }
+  if let Some(message) = payload.downcast_ref::<String>() {
+    return message.clone();
+  }
+  String::from("panicked with a non string payload")
+}